@@ -0,0 +1,384 @@
+use clap::ArgMatches;
+
+use super::corpus;
+use super::format::{self, InputFormat};
+use super::utils;
+
+const MAX_LAYERS: usize = 4;
+const MAX_MIME_DEPTH: usize = 8;
+const HTML_TAGS: [&str; 6] = ["<html", "<body", "<p>", "<p ", "<br", "<div"];
+const EMAIL_HEADERS: [&str; 5] = ["from:", "to:", "subject:", "mime-version:", "content-type:"];
+const LIGATURES: [(char, &str); 7] = [
+  ('\u{fb00}', "ff"), ('\u{fb01}', "fi"), ('\u{fb02}', "fl"), ('\u{fb03}', "ffi"),
+  ('\u{fb04}', "ffl"), ('\u{fb05}', "st"), ('\u{fb06}', "st"),
+];
+const TYPOGRAPHY: [(char, &str); 8] = [
+  ('\u{2018}', "'"), ('\u{2019}', "'"), ('\u{201c}', "\""), ('\u{201d}', "\""),
+  ('\u{2026}', "..."), ('\u{a0}', " "), ('\u{ad}', ""), ('\u{2011}', "-"),
+];
+const MARKDOWN_MARKERS: [char; 4] = ['*', '_', '~', '`'];
+
+pub fn get_input_format(matches: &ArgMatches, alg: utils::Algorithm) -> Result<InputFormat, String> {
+  let format = format::get_input_format_from_string(utils::get_value(matches, "input_format")?)?;
+  match alg {
+    utils::Algorithm::Invisible(_) | utils::Algorithm::Synonym if format == InputFormat::Auto => Ok(InputFormat::Plain),
+    _ => Ok(format),
+  }
+  // the cover algorithms hide the payload in characters and words the
+  // adapters would change, so they are only adapted when asked for.
+}
+
+fn is_email(text: &str) -> bool {
+  let headers = match text.replace("\r\n", "\n").split("\n\n").next() {
+    Some(headers) => headers.to_lowercase(),
+    None => return false,
+  };
+  let first_line = headers.lines().next().unwrap_or("");
+  let is_header = first_line.split(':').next().is_some_and(|name| {
+    !name.is_empty() && first_line.contains(':') && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+  });
+  is_header && headers.lines().any(|line| EMAIL_HEADERS.iter().any(|header| line.starts_with(header)))
+}
+
+fn is_quoted(text: &str) -> bool {
+  let lines: Vec<&str> = text.lines().map(|line| line.trim_start()).filter(|line| !line.is_empty()).collect();
+  !lines.is_empty() && lines.iter().filter(|line| line.starts_with('>')).count() * 2 >= lines.len()
+}
+
+fn is_markdown(text: &str) -> bool {
+  let has_heading = text.lines().any(|line| {
+    let hashes = line.chars().take_while(|c| *c == '#').count();
+    (1..=6).contains(&hashes) && line[hashes..].starts_with(' ')
+  });
+  let has_escape = text.as_bytes().windows(2).any(|pair| pair[0] == b'\\' && pair[1].is_ascii_punctuation());
+  has_heading || has_escape || text.contains("**") || text.contains("](")
+}
+
+pub fn detect_format(text: &str) -> InputFormat {
+  let lowercase = text.to_lowercase();
+  if is_email(text) {
+    InputFormat::Email
+  } else if HTML_TAGS.iter().any(|tag| lowercase.contains(tag)) {
+    InputFormat::Html
+  } else if is_quoted(text) {
+    InputFormat::Quoted
+  } else if text.contains('\u{c}') || text.chars().any(|c| LIGATURES.iter().any(|(ligature, _)| *ligature == c)) {
+    InputFormat::Pdf
+  } else if is_markdown(text) {
+    InputFormat::Markdown
+  } else {
+    InputFormat::Plain
+  }
+  // emails and html come first, as their bodies can look like any
+  // of the others.
+}
+
+pub fn normalize_typography(text: &str) -> String {
+  let mut normalized = String::with_capacity(text.len());
+  for c in text.chars() {
+    match TYPOGRAPHY.iter().find(|(from, _)| *from == c) {
+      Some((_, to)) => normalized.push_str(to),
+      None => normalized.push(c),
+    }
+  }
+  // editors, forums and pdf writers turn quotes and dots into their
+  // typographic forms, the encoder only ever writes the plain ones.
+
+  normalized
+}
+
+pub fn decode_base64(text: &str) -> Result<Vec<u8>, String> {
+  let mut decoded = vec![];
+  let mut buffer = 0u32;
+  let mut num_bits = 0;
+  for c in text.chars().filter(|c| !c.is_whitespace()) {
+    let value = match c {
+      'A'..='Z' => c as u32 - 'A' as u32,
+      'a'..='z' => c as u32 - 'a' as u32 + 26,
+      '0'..='9' => c as u32 - '0' as u32 + 52,
+      '+' => 62,
+      '/' => 63,
+      '=' => break,
+      _ => return Err(format!("'{}' is not a base64 character", c)),
+    };
+    buffer = (buffer << 6) | value;
+    num_bits += 6;
+    if num_bits >= 8 {
+      num_bits -= 8;
+      decoded.push((buffer >> num_bits) as u8);
+      buffer &= (1 << num_bits) - 1;
+    }
+  }
+
+  Ok(decoded)
+}
+
+fn split_headers(message: &str) -> (String, &str) {
+  let (headers, body) = match message.find("\n\n") {
+    Some(end) => (&message[..end], &message[end + 2..]),
+    None => (message, ""),
+  };
+  (headers.replace("\n ", " ").replace("\n\t", " "), body)
+  // long headers can be folded over several lines
+}
+
+fn get_header(headers: &str, name: &str) -> Option<String> {
+  headers.lines()
+    .find(|line| line.to_lowercase().starts_with(&format!("{}:", name)))
+    .map(|line| line[name.len() + 1..].trim().to_string())
+}
+
+fn get_parameter(value: &str, name: &str) -> Option<String> {
+  value.split(';')
+    .skip(1)
+    .filter_map(|parameter| parameter.trim().split_once('='))
+    .find(|(key, _)| key.trim().eq_ignore_ascii_case(name))
+    .map(|(_, value)| value.trim().trim_matches('"').to_string())
+}
+
+fn get_mime_text(message: &str, depth: usize) -> Result<Option<(String, bool)>, String> {
+  if depth > MAX_MIME_DEPTH {
+    return Err("The email has too many nested parts".into());
+  }
+  let (headers, body) = split_headers(message);
+  let content_type = get_header(&headers, "content-type").unwrap_or_else(|| String::from("text/plain"));
+  let media_type = content_type.to_lowercase();
+
+  if media_type.starts_with("multipart/") {
+    let boundary = match get_parameter(&content_type, "boundary") {
+      Some(boundary) => format!("--{}", boundary),
+      None => return Err("The email is multipart but has no boundary".into()),
+    };
+    let mut parts = vec![];
+    let mut part: Option<Vec<&str>> = None;
+    for line in body.lines() {
+      if line.trim_end() == format!("{}--", boundary) {
+        break;
+      }
+      if line.trim_end() == boundary {
+        parts.extend(part.take());
+        part = Some(vec![]);
+      } else if let Some(part) = part.as_mut() {
+        part.push(line);
+      }
+    }
+    parts.extend(part);
+    // the text before the first boundary is a note for mail clients
+    // that can not read mime, and is not part of the message.
+
+    let mut html = None;
+    for part in parts {
+      match get_mime_text(&part.join("\n"), depth + 1)? {
+        Some((text, false)) => return Ok(Some((text, false))),
+        Some(text) => { html.get_or_insert(text); },
+        None => {},
+      }
+    }
+    return Ok(html);
+    // plain text is preferred, the html alternative is only used when
+    // there is nothing else.
+  }
+  if !media_type.starts_with("text/") {
+    return Ok(None);
+  }
+
+  let encoding = get_header(&headers, "content-transfer-encoding").map(|encoding| encoding.to_lowercase());
+  let text = match encoding.as_deref() {
+    Some("quoted-printable") => format::decode_quoted_printable(body),
+    Some("base64") => String::from_utf8_lossy(&decode_base64(body)?).into_owned(),
+    _ => body.to_string(),
+  };
+  Ok(Some((text, media_type.starts_with("text/html"))))
+}
+
+pub fn get_email_body(email: &str) -> Result<String, String> {
+  let email = email.replace("\r\n", "\n");
+  if !email.contains("\n\n") {
+    return Err("The email has no empty line after the headers".into());
+  }
+  match get_mime_text(&email, 0)? {
+    Some((text, false)) => Ok(text),
+    Some((html, true)) => Ok(read_html(&html)),
+    None => Err("The email has no text part".into()),
+  }
+}
+
+pub fn read_html(html: &str) -> String {
+  normalize_typography(&corpus::html_to_text(html))
+}
+
+pub fn strip_quotes(text: &str) -> String {
+  let lines: Vec<&str> = text.lines().map(|line| {
+    let mut line = line.trim_start();
+    while let Some(rest) = line.strip_prefix('>') {
+      line = rest.trim_start();
+    }
+    line
+  }).collect();
+  // replies can be quoted again, so a line can start with several >.
+
+  lines.join("\n")
+}
+
+fn strip_links(line: &str) -> String {
+  let mut stripped = String::with_capacity(line.len());
+  let mut rest = line;
+  while let Some(open) = rest.find('[') {
+    if rest[..open].ends_with('\\') {
+      stripped.push_str(&rest[..open + 1]);
+      rest = &rest[open + 1..];
+      continue;
+    }
+    let close = match rest[open..].find("](") {
+      Some(close) => open + close,
+      None => break,
+    };
+    let end = match rest[close..].find(')') {
+      Some(end) => close + end,
+      None => break,
+    };
+    let start = if rest[..open].ends_with('!') { open - 1 } else { open };
+    stripped.push_str(&rest[..start]);
+    stripped.push_str(&rest[open + 1..close]);
+    rest = &rest[end + 1..];
+  }
+  stripped.push_str(rest);
+  // links and images keep their text and lose the address.
+
+  stripped
+}
+
+fn strip_emphasis(word: &str) -> &str {
+  let word = word.trim_start_matches(MARKDOWN_MARKERS);
+  let mut end = word.len();
+  while end > 0 && word[..end].ends_with(MARKDOWN_MARKERS) && !word[..end - 1].ends_with('\\') {
+    end -= 1;
+  }
+  &word[..end]
+  // an escaped marker belongs to the word and is kept.
+}
+
+pub fn strip_markdown(text: &str) -> String {
+  let mut lines = vec![];
+  for line in text.lines() {
+    let mut line = line.trim_start();
+    if line.starts_with("```") || line.starts_with("~~~") {
+      continue;
+    }
+    if line.len() >= 3 && line.chars().all(|c| "-*_=".contains(c)) {
+      continue;
+    }
+    // code fences, rules and the lines under headings are dropped,
+    // the text between them is kept.
+
+    while let Some(rest) = line.strip_prefix('>') {
+      line = rest.trim_start();
+    }
+    let hashes = line.chars().take_while(|c| *c == '#').count();
+    if (1..=6).contains(&hashes) && line[hashes..].starts_with(' ') {
+      line = line[hashes..].trim_end_matches('#').trim();
+    }
+    for bullet in ["- ", "* ", "+ "] {
+      if let Some(rest) = line.strip_prefix(bullet) {
+        line = rest;
+        break;
+      }
+    }
+
+    let line = strip_links(line);
+    let words: Vec<&str> = line.split_whitespace().map(strip_emphasis).filter(|word| !word.is_empty()).collect();
+    lines.push(words.join(" "));
+  }
+
+  normalize_typography(&format::unescape_markdown(&lines.join("\n")))
+}
+
+pub fn clean_pdf_text(text: &str) -> String {
+  let mut text = text.replace('\r', "");
+  for (ligature, letters) in LIGATURES.iter() {
+    text = text.replace(*ligature, letters);
+  }
+
+  let mut lines = vec![];
+  for page in text.split('\u{c}') {
+    let mut page_lines: Vec<&str> = page.lines().map(|line| line.trim()).filter(|line| !line.is_empty()).collect();
+    if page_lines.first().is_some_and(|line| line.chars().all(|c| c.is_ascii_digit())) {
+      page_lines.remove(0);
+    }
+    if page_lines.last().is_some_and(|line| line.chars().all(|c| c.is_ascii_digit())) {
+      page_lines.pop();
+    }
+    lines.extend(page_lines);
+  }
+  // page numbers sit on a line of their own at the top or bottom
+  // of a page.
+
+  let mut cleaned = String::with_capacity(text.len());
+  for (i, line) in lines.iter().enumerate() {
+    let next_is_lowercase = lines.get(i + 1).is_some_and(|next| next.starts_with(|c: char| c.is_lowercase()));
+    let is_hyphenated = line.strip_suffix('-').is_some_and(|rest| rest.ends_with(char::is_alphabetic));
+    if is_hyphenated && next_is_lowercase {
+      cleaned.push_str(&line[..line.len() - 1]);
+    } else {
+      cleaned.push_str(line);
+      cleaned.push('\n');
+    }
+  }
+  // a word broken over two lines is joined again. a word that had a
+  // hyphen of its own can not be told apart, and loses it.
+
+  normalize_typography(&cleaned)
+}
+
+fn read_auto(contents: &str) -> Result<String, String> {
+  let mut text = contents.to_string();
+  let mut layers = vec![];
+  for _ in 0..MAX_LAYERS {
+    let format = detect_format(&text);
+    let name = format::get_input_format_name(format);
+    if format == InputFormat::Plain || layers.contains(&name) {
+      break;
+    }
+    text = read_format(&text, format)?;
+    layers.push(name);
+  }
+  // an email can hold html, and a reply can quote markdown, so the
+  // text is read again until nothing new is found. no format is read
+  // twice, text that only looks like markup would lose characters.
+
+  // print summary
+  if layers.is_empty() {
+    println!("input format: plain, no other format was found");
+  } else {
+    println!("input format: {}", layers.join(", then "));
+  }
+
+  Ok(text)
+}
+
+pub fn read_text(contents: &str, format: InputFormat) -> Result<String, String> {
+  match format {
+    InputFormat::Plain => Ok(contents.to_string()),
+    InputFormat::Auto => read_auto(contents),
+    _ => {
+      println!("input format: {}", format::get_input_format_name(format));
+      read_format(contents, format)
+    },
+  }
+  // the adapters change the text, so it is always printed
+  // which of them were used.
+}
+
+pub fn read_format(contents: &str, format: InputFormat) -> Result<String, String> {
+  match format {
+    InputFormat::Plain | InputFormat::Wrap => Ok(contents.to_string()),
+    InputFormat::Markdown => Ok(strip_markdown(contents)),
+    InputFormat::Html => Ok(read_html(contents)),
+    InputFormat::Email => get_email_body(contents),
+    InputFormat::Quoted => Ok(strip_quotes(contents)),
+    InputFormat::Pdf => Ok(clean_pdf_text(contents)),
+    InputFormat::Auto => read_auto(contents),
+  }
+  // the decoder only looks at the words, so line breaks
+  // do not need to be undone.
+}
@@ -0,0 +1,327 @@
+use std::collections::HashSet;
+
+use clap::ArgMatches;
+
+use super::utils;
+use super::corpus;
+use super::encode;
+use super::tokenizer;
+
+const MAX_WORD_LENGTH: usize = 20;
+const BACKOFF_PENALTY: f64 = 0.4;
+pub const HELD_OUT_FRACTION: f64 = 0.2;
+
+pub struct TextStats {
+  pub letter_counts: [f64; 26],
+  pub length_counts: [f64; MAX_WORD_LENGTH + 1],
+  pub num_words: usize,
+  pub num_types: usize,
+  pub num_repeats: usize,
+  pub longest_run: usize,
+}
+
+pub struct Analysis {
+  pub perplexity: f64,
+  pub baseline_perplexity: f64,
+  pub chi_square: f64,
+  pub letter_divergence: f64,
+  pub length_divergence: f64,
+  pub type_token_ratio: f64,
+  pub baseline_type_token_ratio: f64,
+  pub repeat_rate: f64,
+  pub baseline_repeat_rate: f64,
+  pub longest_run: usize,
+}
+
+pub fn get_text_words(text: &str, policy: &tokenizer::TokenizerPolicy) -> String {
+  tokenizer::tokenize(text, policy)
+  // same tokenizer as the mimic text gets when it is loaded, so
+  // both texts are split into words the same way.
+}
+
+pub fn get_text_stats(words: &[&str]) -> TextStats {
+  let mut letter_counts = [0.0; 26];
+  let mut length_counts = [0.0; MAX_WORD_LENGTH + 1];
+  let mut types = HashSet::new();
+  let mut num_words = 0;
+  let mut num_repeats = 0;
+  let mut current_run = 1;
+  let mut longest_run = 1;
+  let mut last_word = "";
+
+  for word in words {
+    if utils::is_punctuation(word) {
+      continue;
+    }
+
+    num_words += 1;
+    types.insert(*word);
+    length_counts[word.chars().count().min(MAX_WORD_LENGTH)] += 1.0;
+    for c in word.chars() {
+      if c.is_ascii_lowercase() {
+        letter_counts[(c as u8 - b'a') as usize] += 1.0;
+      }
+    }
+
+    if *word == last_word {
+      num_repeats += 1;
+      current_run += 1;
+      if current_run > longest_run {
+        longest_run = current_run;
+      }
+    } else {
+      current_run = 1;
+    }
+    last_word = word;
+    // a run is the same word several times in a row, ie: "the the the".
+    // punctuation is skipped so "no , no" also counts.
+  }
+
+  TextStats {
+    letter_counts,
+    length_counts,
+    num_words,
+    num_types: types.len(),
+    num_repeats,
+    longest_run: if num_words == 0 { 0 } else { longest_run },
+  }
+}
+
+pub fn get_word_log_probability(
+  word: &str,
+  current_words: &[&str],
+  model: &encode::Model,
+  n: usize,
+) -> f64 {
+  let mut backoff_penalty = 0.0;
+  for use_n in (1..=n).rev() {
+    let context_len = (use_n - 1).min(current_words.len());
+    let context = &current_words[current_words.len() - context_len..];
+    let p = encode::get_probability_of(word, context, model);
+    if p > 0.0 {
+      return p.ln() + backoff_penalty;
+    }
+    backoff_penalty += BACKOFF_PENALTY.ln();
+  }
+  // stupid backoff: every time the context has to be shortened, the
+  // probability gets multiplied by a constant penalty. words that are not
+  // in the mimic text at all get the probability of a word seen once.
+
+  (1.0 / (model.total_words + model.unique_words.len() as f64)).ln() + backoff_penalty
+}
+
+pub fn get_word_log_probabilities(
+  words: &[&str],
+  model: &encode::Model,
+  n: usize,
+) -> Vec<f64> {
+  let mut log_probabilities = vec![];
+  for i in 0..words.len() {
+    let context_start = (i + 1).saturating_sub(n);
+    log_probabilities.push(get_word_log_probability(
+      words[i],
+      &words[context_start..i],
+      model,
+      n,
+    ));
+  }
+
+  log_probabilities
+}
+
+pub fn get_perplexity(log_probabilities: &[f64]) -> f64 {
+  if log_probabilities.is_empty() {
+    return 0.0;
+  }
+
+  let sum: f64 = log_probabilities.iter().sum();
+  (-sum / log_probabilities.len() as f64).exp()
+}
+
+pub fn normalize(counts: &[f64]) -> Vec<f64> {
+  let total: f64 = counts.iter().sum();
+  if total == 0.0 {
+    return vec![0.0; counts.len()];
+  }
+
+  counts.iter().map(|c| c / total).collect()
+}
+
+pub fn get_chi_square(observed: &[f64], expected_counts: &[f64]) -> f64 {
+  let total_observed: f64 = observed.iter().sum();
+  let expected = normalize(expected_counts);
+  let mut chi_square = 0.0;
+  for (o, p) in observed.iter().zip(expected.iter()) {
+    let e = p * total_observed;
+    if e > 0.0 {
+      chi_square += (o - e) * (o - e) / e;
+    }
+  }
+
+  chi_square
+}
+
+pub fn get_js_divergence(p_counts: &[f64], q_counts: &[f64]) -> f64 {
+  let p = normalize(p_counts);
+  let q = normalize(q_counts);
+  let mut divergence = 0.0;
+  for (pi, qi) in p.iter().zip(q.iter()) {
+    let mi = (pi + qi) / 2.0;
+    if *pi > 0.0 {
+      divergence += 0.5 * pi * (pi / mi).ln();
+    }
+    if *qi > 0.0 {
+      divergence += 0.5 * qi * (qi / mi).ln();
+    }
+  }
+  // jensen-shannon divergence: 0 if the distributions are the same,
+  // ln(2) if they have nothing in common.
+
+  divergence
+}
+
+pub fn get_type_token_ratio(stats: &TextStats) -> f64 {
+  if stats.num_words == 0 {
+    return 0.0;
+  }
+
+  stats.num_types as f64 / stats.num_words as f64
+}
+
+pub fn get_repeat_rate(stats: &TextStats) -> f64 {
+  if stats.num_words == 0 {
+    return 0.0;
+  }
+
+  stats.num_repeats as f64 / stats.num_words as f64
+}
+
+pub fn get_baseline_words<'a>(held_out_words: &[&'a str], num_words: usize) -> Vec<&'a str> {
+  let num_words = num_words.min(held_out_words.len());
+  let start = (held_out_words.len() - num_words) / 2;
+  // type token ratio and perplexity depend on how long the text is,
+  // so compare against a piece of the mimic text of the same length.
+  // it comes from the held out text, the model has seen every
+  // other piece, which would make its perplexity too low.
+
+  held_out_words[start..start + num_words].to_vec()
+}
+
+pub fn analyze_text(
+  words: &[&str],
+  corpus_stats: &TextStats,
+  held_out_words: &[&str],
+  model: &encode::Model,
+  n: usize,
+) -> Analysis {
+  let baseline_words = get_baseline_words(held_out_words, words.len());
+  let text_stats = get_text_stats(words);
+  let baseline_stats = get_text_stats(&baseline_words);
+
+  let log_probabilities = get_word_log_probabilities(words, model, n);
+  let baseline_log_probabilities = get_word_log_probabilities(&baseline_words, model, n);
+
+  let chi_square = get_chi_square(&text_stats.letter_counts, &corpus_stats.letter_counts);
+  let total_letters: f64 = text_stats.letter_counts.iter().sum();
+
+  Analysis {
+    perplexity: get_perplexity(&log_probabilities),
+    baseline_perplexity: get_perplexity(&baseline_log_probabilities),
+    chi_square,
+    letter_divergence: if total_letters > 0.0 { chi_square / total_letters } else { 0.0 },
+    length_divergence: get_js_divergence(&text_stats.length_counts, &corpus_stats.length_counts),
+    type_token_ratio: get_type_token_ratio(&text_stats),
+    baseline_type_token_ratio: get_type_token_ratio(&baseline_stats),
+    repeat_rate: get_repeat_rate(&text_stats),
+    baseline_repeat_rate: get_repeat_rate(&baseline_stats),
+    longest_run: text_stats.longest_run,
+  }
+}
+
+pub fn get_component_scores(analysis: &Analysis) -> Vec<(&'static str, f64)> {
+  let perplexity_ratio = if analysis.baseline_perplexity > 0.0 {
+    analysis.perplexity / analysis.baseline_perplexity
+  } else {
+    1.0
+  };
+  let ttr_difference = if analysis.baseline_type_token_ratio > 0.0 {
+    (analysis.type_token_ratio - analysis.baseline_type_token_ratio).abs() / analysis.baseline_type_token_ratio
+  } else {
+    0.0
+  };
+  let extra_repeats = (analysis.repeat_rate - analysis.baseline_repeat_rate).max(0.0);
+
+  vec![
+    ("perplexity", 1.0 - (-perplexity_ratio.ln().max(0.0)).exp()),
+    ("letter frequency", 1.0 - (-analysis.letter_divergence * 20.0).exp()),
+    ("word length", 1.0 - (-analysis.length_divergence * 20.0).exp()),
+    ("type/token ratio", ttr_difference.min(1.0)),
+    ("repeated words", 1.0 - (-extra_repeats * 50.0).exp()),
+  ]
+  // every measurement is squashed into 0 (looks like the mimic text)
+  // to 1 (clearly different). the constants are chosen so that
+  // a piece of the mimic text itself scores close to 0.
+}
+
+pub fn get_detectability_score(analysis: &Analysis) -> f64 {
+  let component_scores = get_component_scores(analysis);
+  let sum: f64 = component_scores.iter().map(|(_, score)| score).sum();
+
+  100.0 * sum / component_scores.len() as f64
+}
+
+pub fn analyze(matches: &ArgMatches) -> Result<(), String> {
+  let file = utils::get_value(matches, "file")?;
+  let n_depth = utils::get_numerical_value(matches, "n")?;
+
+  let policy = tokenizer::get_policy(matches)?;
+  let text = get_text_words(&utils::get_file_contents_as_string(file)?, &policy);
+  let (word_file_data, held_out) = corpus::hold_out(&corpus::load_corpus(matches)?, HELD_OUT_FRACTION);
+  let model = encode::generate_model(&word_file_data, n_depth);
+
+  let words: Vec<&str> = text.split_whitespace().collect();
+  let corpus_words: Vec<&str> = word_file_data.text.split_whitespace().collect();
+  let held_out_words: Vec<&str> = held_out.split_whitespace().collect();
+  if words.is_empty() {
+    return Err(format!("'{}' does not contain any words", file));
+  }
+  if held_out_words.len() < words.len() {
+    println!(
+      "Warning: the held out part of the mimic text has {} words, less than the {} words of the text",
+      held_out_words.len(),
+      words.len(),
+    );
+  }
+
+  let corpus_stats = get_text_stats(&corpus_words);
+  let analysis = analyze_text(&words, &corpus_stats, &held_out_words, &model, n_depth);
+
+  let log_probabilities = get_word_log_probabilities(&words, &model, n_depth);
+  let mut surprising: Vec<(usize, f64)> = log_probabilities.iter().cloned().enumerate().collect();
+  surprising.sort_by(|a, b| a.1.partial_cmp(&b.1).unwrap());
+
+  println!(
+    "\nanalyzed {} words against a mimic text of {} words ({} unique), with {} words held out",
+    words.len(),
+    corpus_words.len(),
+    model.unique_words.len(),
+    held_out_words.len(),
+  );
+  println!("\nperplexity: {:.2} (mimic text of the same length: {:.2})", analysis.perplexity, analysis.baseline_perplexity);
+  println!("most surprising words:");
+  for (i, log_p) in surprising.iter().take(5) {
+    println!("  {:>8.1}  {}", (-log_p).exp(), words[*i]);
+  }
+  println!("\nletter frequency chi-square: {:.2} ({:.4} per letter)", analysis.chi_square, analysis.letter_divergence);
+  println!("word length divergence: {:.4}", analysis.length_divergence);
+  println!("type/token ratio: {:.3} (mimic text of the same length: {:.3})", analysis.type_token_ratio, analysis.baseline_type_token_ratio);
+  println!("repeated words: {:.4} per word (mimic text of the same length: {:.4}), longest run: {}", analysis.repeat_rate, analysis.baseline_repeat_rate, analysis.longest_run);
+
+  println!("\nscore breakdown (0 = like the mimic text, 1 = clearly different):");
+  for (name, score) in get_component_scores(&analysis) {
+    println!("  {:<18}{:.3}", name, score);
+  }
+  println!("\ndetectability score: {:.1} / 100\n", get_detectability_score(&analysis));
+
+  Ok(())
+}
@@ -0,0 +1,386 @@
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use clap::ArgMatches;
+use arrayref::array_ref;
+
+use super::utils;
+
+const ARCHIVE_MAGIC: [u8; 4] = *b"tsar";
+const ARCHIVE_VERSION: u8 = 1;
+
+pub struct Entry {
+  pub name: String,
+  pub mode: u32,
+  pub mtime: u64,
+  pub data: Vec<u8>,
+}
+
+pub fn get_mode(metadata: &fs::Metadata) -> u32 {
+  #[cfg(unix)]
+  {
+    use std::os::unix::fs::PermissionsExt;
+    metadata.permissions().mode() & 0o777
+  }
+  #[cfg(not(unix))]
+  {
+    if metadata.permissions().readonly() { 0o444 } else { 0o644 }
+  }
+}
+
+pub fn get_mtime(metadata: &fs::Metadata) -> u64 {
+  metadata.modified().ok()
+    .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+    .map(|duration| duration.as_secs())
+    .unwrap_or(0)
+}
+
+pub fn add_path(path: &Path, name: String, entries: &mut Vec<Entry>) -> Result<(), String> {
+  let metadata = fs::symlink_metadata(path).map_err(|_| format!("Failed to read file: '{}'", path.display()))?;
+
+  if metadata.file_type().is_symlink() {
+    println!("Warning: skipping '{}', symlinks are not stored", path.display());
+    return Ok(());
+  }
+  // following a symlink that points to a directory above it
+  // would add the same files again and again, forever.
+
+  if metadata.is_dir() {
+    let mut children = vec![];
+    let dir = fs::read_dir(path).map_err(|_| format!("Failed to read directory: '{}'", path.display()))?;
+    for child in dir {
+      let child = child.map_err(|_| format!("Failed to read directory: '{}'", path.display()))?;
+      children.push(child.path());
+    }
+    children.sort();
+    // read_dir has no fixed order, sorting makes the
+    // same directory always give the same payload.
+
+    for child in children {
+      let child_name = child.file_name().unwrap().to_string_lossy().into_owned();
+      add_path(&child, format!("{}/{}", name, child_name), entries)?;
+    }
+    return Ok(());
+  }
+
+  entries.push(Entry {
+    data: utils::get_file_contents(&path.to_string_lossy())?,
+    mode: get_mode(&metadata),
+    mtime: get_mtime(&metadata),
+    name,
+  });
+
+  Ok(())
+}
+
+pub fn collect_entries(paths: &[&str]) -> Result<Vec<Entry>, String> {
+  let mut entries = vec![];
+  for path_str in paths {
+    let path = Path::new(path_str);
+    let name = match path.canonicalize().ok().as_ref().and_then(|p| p.file_name()) {
+      Some(name) => name.to_string_lossy().into_owned(),
+      None => return Err(format!("Can not add '{}' to an archive, it has no name", path_str)),
+    };
+    add_path(path, name, &mut entries)?;
+  }
+  // every path is stored by its own name, so a directory keeps its
+  // structure under its name, and the place it was in is not leaked.
+
+  if entries.is_empty() {
+    return Err("There are no files to add to the archive".into());
+  }
+
+  for (i, entry) in entries.iter().enumerate() {
+    if entries[..i].iter().any(|other| other.name == entry.name) {
+      return Err(format!("More than one file would be stored as '{}'", entry.name));
+    }
+  }
+
+  Ok(entries)
+}
+
+pub fn to_bytes(entries: &[Entry]) -> Vec<u8> {
+  let mut bytes = ARCHIVE_MAGIC.to_vec();
+  bytes.push(ARCHIVE_VERSION);
+  bytes.extend_from_slice(&(entries.len() as u32).to_be_bytes());
+  for entry in entries {
+    bytes.extend_from_slice(&(entry.name.len() as u16).to_be_bytes());
+    bytes.extend_from_slice(entry.name.as_bytes());
+    bytes.extend_from_slice(&entry.mode.to_be_bytes());
+    bytes.extend_from_slice(&entry.mtime.to_be_bytes());
+    bytes.extend_from_slice(&(entry.data.len() as u64).to_be_bytes());
+    bytes.extend_from_slice(&entry.data);
+  }
+  // magic, version, number of files, then for every file:
+  // name length, name, mode, mtime, size, and the data.
+
+  bytes
+}
+
+pub fn take<'a>(data: &'a [u8], position: &mut usize, length: usize) -> Result<&'a [u8], String> {
+  if data.len() - *position < length {
+    return Err("The archive is cut off".into());
+  }
+  let bytes = &data[*position..*position + length];
+  *position += length;
+
+  Ok(bytes)
+}
+
+pub fn from_bytes(data: &[u8]) -> Result<Vec<Entry>, String> {
+  if data.len() <= ARCHIVE_MAGIC.len() || data[..ARCHIVE_MAGIC.len()] != ARCHIVE_MAGIC {
+    return Err("The archive is damaged".into());
+  }
+  if data[ARCHIVE_MAGIC.len()] != ARCHIVE_VERSION {
+    return Err(format!("Unknown archive version {}", data[ARCHIVE_MAGIC.len()]));
+  }
+
+  let mut position = ARCHIVE_MAGIC.len() + 1;
+  let num_entries = u32::from_be_bytes(*array_ref!(take(data, &mut position, 4)?, 0, 4));
+  let mut entries = vec![];
+  for _ in 0..num_entries {
+    let name_len = u16::from_be_bytes(*array_ref!(take(data, &mut position, 2)?, 0, 2)) as usize;
+    let name = String::from_utf8(take(data, &mut position, name_len)?.to_vec())
+      .map_err(|_| String::from("A file name in the archive is not valid UTF-8"))?;
+    let mode = u32::from_be_bytes(*array_ref!(take(data, &mut position, 4)?, 0, 4));
+    let mtime = u64::from_be_bytes(*array_ref!(take(data, &mut position, 8)?, 0, 8));
+    let size = u64::from_be_bytes(*array_ref!(take(data, &mut position, 8)?, 0, 8)) as usize;
+    let data = take(data, &mut position, size)?.to_vec();
+    entries.push(Entry { name, mode, mtime, data });
+  }
+
+  Ok(entries)
+}
+
+pub fn get_safe_path(output_dir: &Path, name: &str) -> Result<PathBuf, String> {
+  let mut path = output_dir.to_path_buf();
+  for component in name.split('/') {
+    if component.is_empty() || component == "." || component == ".." || component.contains('\\') || component.contains(':') {
+      return Err(format!("Refusing to extract '{}', it would end up outside of the output directory", name));
+    }
+    path.push(component);
+  }
+  // the names come from whoever made the stegotext, so a name like
+  // ../../.bashrc must not be able to write anywhere else.
+
+  Ok(path)
+}
+
+pub fn create_parent_dirs(output_dir: &Path, path: &Path) -> Result<(), String> {
+  let parent = match path.parent() {
+    Some(parent) => parent,
+    None => return Ok(()),
+  };
+  let relative = parent.strip_prefix(output_dir).unwrap_or(Path::new(""));
+
+  let mut dir = output_dir.to_path_buf();
+  for component in relative.components() {
+    dir.push(component);
+    match fs::symlink_metadata(&dir) {
+      Ok(metadata) if metadata.file_type().is_symlink() => {
+        return Err(format!("Refusing to extract into '{}', it is a symlink", dir.display()));
+      },
+      Ok(metadata) if !metadata.is_dir() => {
+        return Err(format!("Can not extract into '{}', it is not a directory", dir.display()));
+      },
+      Ok(_) => {},
+      Err(_) => fs::create_dir(&dir).map_err(|e| format!("Failed to create '{}': {}", dir.display(), e))?,
+    }
+  }
+  // a symlink in the output directory, ie: one that an earlier entry
+  // or someone else put there, could point anywhere, so every
+  // directory on the way is checked instead of followed.
+
+  Ok(())
+}
+
+pub fn extract(entries: &[Entry], output_dir: &str, force: bool) -> Result<(), String> {
+  let output_dir = Path::new(output_dir);
+  fs::create_dir_all(output_dir).map_err(|e| format!("Failed to create '{}': {}", output_dir.display(), e))?;
+
+  for entry in entries {
+    let path = get_safe_path(output_dir, &entry.name)?;
+    create_parent_dirs(output_dir, &path)?;
+
+    match fs::symlink_metadata(&path) {
+      Ok(metadata) if metadata.file_type().is_symlink() => {
+        return Err(format!("Refusing to extract to '{}', it is a symlink", path.display()));
+      },
+      Ok(metadata) if metadata.is_dir() => {
+        return Err(format!("Can not extract to '{}', it is a directory", path.display()));
+      },
+      Ok(_) if !force => {
+        return Err(format!("'{}' already exists, use --force to overwrite it", path.display()));
+      },
+      Ok(_) => fs::remove_file(&path).map_err(|e| format!("Failed to remove '{}': {}", path.display(), e))?,
+      Err(_) => {},
+    }
+    // with --force, the old file is removed instead of written
+    // through, so a hard link to a file elsewhere stays as it is.
+
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+      use std::os::unix::fs::OpenOptionsExt;
+      options.mode(0o600);
+    }
+    let mut file = options.open(&path).map_err(|e| format!("Failed to write '{}': {}", path.display(), e))?;
+    file.write_all(&entry.data).map_err(|e| format!("Failed to write '{}': {}", path.display(), e))?;
+
+    let _ = file.set_modified(SystemTime::UNIX_EPOCH + Duration::from_secs(entry.mtime));
+    // some filesystems can not store the time, that is not worth failing for
+
+    #[cfg(unix)]
+    {
+      use std::os::unix::fs::PermissionsExt;
+      file.set_permissions(fs::Permissions::from_mode(entry.mode & 0o777))
+        .map_err(|e| format!("Failed to set permissions of '{}': {}", path.display(), e))?;
+    }
+  }
+
+  Ok(())
+}
+
+pub fn get_payload(matches: &ArgMatches) -> Result<(utils::PayloadKind, Vec<u8>), String> {
+  let files: Vec<&str> = matches.values_of("file").map(|values| values.collect()).unwrap_or_default();
+  let file = utils::get_value(matches, "file")?;
+
+  if files.len() == 1 && !matches.is_present("archive") && !Path::new(file).is_dir() {
+    return Ok((utils::PayloadKind::Data, utils::get_file_contents(file)?));
+  }
+  // a single file is encoded as it is, like before, so it can still
+  // be decoded with --output. anything else goes into an archive,
+  // and the header of the payload says so.
+
+  let entries = collect_entries(&files)?;
+  let total_size: usize = entries.iter().map(|entry| entry.data.len()).sum();
+  println!("\narchiving {} files ({} bytes):", entries.len(), total_size);
+  print_entries(&entries);
+
+  Ok((utils::PayloadKind::Archive, to_bytes(&entries)))
+}
+
+pub fn print_entries(entries: &[Entry]) {
+  for entry in entries {
+    println!("  {:o} {:>10} {}", entry.mode, entry.data.len(), entry.name);
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn get_test_entries() -> Vec<Entry> {
+    vec![
+      Entry { name: "notes.txt".into(), mode: 0o644, mtime: 1_600_000_000, data: b"some notes".to_vec() },
+      Entry { name: "dir/empty".into(), mode: 0o600, mtime: 0, data: vec![] },
+      Entry { name: "dir/sub/run.sh".into(), mode: 0o755, mtime: 42, data: vec![0, 1, 2, 255] },
+    ]
+  }
+
+  fn get_test_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("text-steg-test-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+  }
+
+  #[test]
+  fn bytes_round_trip() {
+    let entries = get_test_entries();
+    let decoded = from_bytes(&to_bytes(&entries)).unwrap();
+    assert_eq!(decoded.len(), entries.len());
+    for (entry, decoded) in entries.iter().zip(decoded.iter()) {
+      assert_eq!(entry.name, decoded.name);
+      assert_eq!(entry.mode, decoded.mode);
+      assert_eq!(entry.mtime, decoded.mtime);
+      assert_eq!(entry.data, decoded.data);
+    }
+  }
+
+  #[test]
+  fn truncated_archive_is_an_error() {
+    let bytes = to_bytes(&get_test_entries());
+    for len in 0..bytes.len() {
+      assert!(from_bytes(&bytes[..len]).is_err(), "cut off at {} of {} bytes", len, bytes.len());
+    }
+  }
+
+  #[test]
+  fn malformed_archive_is_an_error() {
+    let bytes = to_bytes(&get_test_entries());
+
+    let mut bad_magic = bytes.clone();
+    bad_magic[0] ^= 1;
+    assert!(from_bytes(&bad_magic).is_err());
+
+    let mut bad_version = bytes.clone();
+    bad_version[ARCHIVE_MAGIC.len()] = ARCHIVE_VERSION + 1;
+    assert!(from_bytes(&bad_version).is_err());
+
+    let mut too_many_entries = bytes.clone();
+    too_many_entries[ARCHIVE_MAGIC.len() + 4] += 1;
+    assert!(from_bytes(&too_many_entries).is_err());
+
+    let mut bad_name = bytes.clone();
+    bad_name[ARCHIVE_MAGIC.len() + 7] = 0xff;
+    assert!(from_bytes(&bad_name).is_err());
+
+    let mut huge_size = to_bytes(&get_test_entries()[..1]);
+    let size_position = ARCHIVE_MAGIC.len() + 7 + "notes.txt".len() + 12;
+    huge_size[size_position..size_position + 8].copy_from_slice(&u64::MAX.to_be_bytes());
+    assert!(from_bytes(&huge_size).is_err());
+  }
+
+  #[test]
+  fn unsafe_names_are_refused() {
+    let output_dir = Path::new("out");
+    for name in &["../escape", "dir/../../escape", "/etc/passwd", "a//b", "c:\\windows", "./a", ""] {
+      assert!(get_safe_path(output_dir, name).is_err(), "{}", name);
+    }
+    assert_eq!(get_safe_path(output_dir, "dir/file").unwrap(), output_dir.join("dir").join("file"));
+  }
+
+  #[test]
+  fn extract_writes_files_and_refuses_to_overwrite() {
+    let dir = get_test_dir("extract");
+    let output_dir = dir.join("out");
+    let output_dir_str = output_dir.to_str().unwrap();
+    let entries = get_test_entries();
+    extract(&entries, output_dir_str, false).unwrap();
+    for entry in &entries {
+      assert_eq!(fs::read(output_dir.join(&entry.name)).unwrap(), entry.data);
+    }
+
+    let changed = vec![Entry { name: "notes.txt".into(), mode: 0o644, mtime: 0, data: b"new notes".to_vec() }];
+    assert!(extract(&changed, output_dir_str, false).is_err());
+    assert_eq!(fs::read(output_dir.join("notes.txt")).unwrap(), b"some notes");
+    extract(&changed, output_dir_str, true).unwrap();
+    assert_eq!(fs::read(output_dir.join("notes.txt")).unwrap(), b"new notes");
+
+    fs::remove_dir_all(dir).unwrap();
+  }
+
+  #[cfg(unix)]
+  #[test]
+  fn extract_does_not_follow_symlinks() {
+    let dir = get_test_dir("extract-symlink");
+    let output_dir = dir.join("out");
+    let outside = dir.join("outside");
+    fs::create_dir_all(&output_dir).unwrap();
+    fs::create_dir_all(&outside).unwrap();
+    std::os::unix::fs::symlink(&outside, output_dir.join("dir")).unwrap();
+    std::os::unix::fs::symlink(outside.join("target"), output_dir.join("link")).unwrap();
+
+    let through_dir = vec![Entry { name: "dir/file".into(), mode: 0o644, mtime: 0, data: b"x".to_vec() }];
+    assert!(extract(&through_dir, output_dir.to_str().unwrap(), true).is_err());
+    let to_link = vec![Entry { name: "link".into(), mode: 0o644, mtime: 0, data: b"x".to_vec() }];
+    assert!(extract(&to_link, output_dir.to_str().unwrap(), true).is_err());
+    assert!(fs::read_dir(&outside).unwrap().next().is_none());
+
+    fs::remove_dir_all(dir).unwrap();
+  }
+}
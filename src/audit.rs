@@ -0,0 +1,235 @@
+use std::fs;
+
+use clap::ArgMatches;
+
+use super::adapter;
+use super::corpus;
+use super::decode;
+use super::tokenizer;
+use super::utils;
+
+const FILE_MAGICS: [(&[u8], &str); 16] = [
+  (b"\x89PNG\r\n\x1a\n", "PNG image"),
+  (b"\xff\xd8\xff", "JPEG image"),
+  (b"GIF87a", "GIF image"),
+  (b"GIF89a", "GIF image"),
+  (b"%PDF-", "PDF document"),
+  (b"PK\x03\x04", "ZIP archive"),
+  (b"\x1f\x8b", "gzip data"),
+  (b"BZh", "bzip2 data"),
+  (b"\xfd7zXZ\x00", "xz data"),
+  (b"7z\xbc\xaf\x27\x1c", "7-zip archive"),
+  (b"\x7fELF", "ELF executable"),
+  (b"RIFF", "RIFF (wav/avi/webp) data"),
+  (b"OggS", "Ogg media"),
+  (b"ID3", "MP3 audio"),
+  (b"-----BEGIN ", "PEM key or certificate"),
+  (b"\x85\x02", "GnuPG encrypted data"),
+];
+
+pub struct Candidate {
+  pub password: String,
+  pub payload: Vec<u8>,
+  pub score: f64,
+  pub reasons: Vec<String>,
+}
+
+pub fn get_entropy(data: &[u8]) -> f64 {
+  let mut counts = [0usize; 256];
+  for byte in data {
+    counts[*byte as usize] += 1;
+  }
+
+  let total = data.len() as f64;
+  let mut entropy = 0.0;
+  for count in counts.iter().filter(|c| **c > 0) {
+    let p = *count as f64 / total;
+    entropy -= p * p.log2();
+  }
+
+  entropy
+}
+
+pub fn get_file_magic(data: &[u8]) -> Option<&'static str> {
+  for (magic, name) in FILE_MAGICS.iter() {
+    if data.starts_with(magic) {
+      return Some(name);
+    }
+  }
+
+  None
+}
+
+pub fn get_printable_ratio(text: &str) -> f64 {
+  let num_chars = text.chars().count();
+  if num_chars == 0 {
+    return 0.0;
+  }
+
+  let num_printable = text.chars().filter(|c| !c.is_control() || c.is_whitespace()).count();
+  num_printable as f64 / num_chars as f64
+}
+
+pub fn score_payload(payload: &[u8]) -> (f64, Vec<String>) {
+  let mut reasons = vec![];
+  if payload.is_empty() {
+    return (0.0, reasons);
+  }
+
+  let entropy = get_entropy(payload);
+  let max_entropy = (payload.len() as f64).log2().clamp(1.0, 8.0);
+  let entropy_score = (1.0 - entropy / max_entropy).max(0.0);
+  reasons.push(format!("entropy {:.2} bits/byte", entropy));
+  // random bytes use up all the entropy they can. a wrong password
+  // gives random looking output, while most real files (and all
+  // text) do not. the maximum depends on the length because a
+  // short payload can not contain every byte value.
+
+  let content_score = if let Some(name) = get_file_magic(payload) {
+    reasons.push(format!("starts like a {}", name));
+    1.0
+  } else if let Ok(text) = std::str::from_utf8(payload) {
+    let printable_ratio = get_printable_ratio(text);
+    reasons.push(format!("valid utf-8, {:.0}% printable", 100.0 * printable_ratio));
+    printable_ratio
+  } else {
+    let num_printable = payload.iter().filter(|b| b.is_ascii_graphic() || b.is_ascii_whitespace()).count();
+    0.5 * num_printable as f64 / payload.len() as f64
+  };
+
+  (100.0 * (0.6 * content_score + 0.4 * entropy_score), reasons)
+}
+
+pub fn get_passwords(file_name: &str) -> Result<Vec<String>, String> {
+  let contents = fs::read(file_name).map_err(|_| format!("Failed to read file: '{}'", file_name))?;
+  let contents = String::from_utf8_lossy(&contents);
+  let mut passwords = vec![];
+  for line in contents.lines() {
+    let password = line.trim_end_matches('\r');
+    if !password.is_empty() && !passwords.iter().any(|p| p == password) {
+      passwords.push(password.to_string());
+    }
+  }
+  // keep every line as it is (passwords can start or end with spaces),
+  // only drop empty lines and duplicates.
+
+  Ok(passwords)
+}
+
+pub fn print_preview(payload: &[u8]) {
+  if let Ok(text) = std::str::from_utf8(payload) {
+    let preview: String = text.chars().take(60).map(|c| if c.is_control() { ' ' } else { c }).collect();
+    println!("      preview: {}", preview);
+  }
+}
+
+pub fn audit(matches: &ArgMatches) -> Result<(), String> {
+  let file = utils::get_value(matches, "file")?;
+  let wordlist = utils::get_value(matches, "wordlist")?;
+  let alg_str = utils::get_value(matches, "algorithm")?;
+  let num_bits = utils::get_numerical_value(matches, "bits")?;
+  let n_depth = utils::get_numerical_value(matches, "n")?;
+  let num_top = utils::get_numerical_value(matches, "top")?;
+
+  if !(1..=8).contains(&num_bits) {
+    return Err(format!("Bits must be between 1 and 8 inclusively, you provided {}", num_bits));
+  }
+
+  let alg = utils::get_algorithm_from_string(alg_str, num_bits)?;
+  let contents = adapter::read_text(&utils::get_file_contents_as_string(file)?, adapter::get_input_format(matches, alg)?)?;
+  let word_file_data = corpus::get_optional_corpus(matches)?;
+  let adaptive_model = decode::get_adaptive_model(word_file_data.as_ref(), alg, n_depth);
+  let synonyms = decode::get_optional_synonyms(matches)?;
+  let policy = tokenizer::get_policy(matches)?;
+  let find = matches.is_present("find");
+  let passwords = get_passwords(wordlist)?;
+
+  let mut candidates = vec![];
+  let mut num_failed = 0;
+  let mut num_headers = 0;
+  for password in passwords.iter() {
+    let decoded = match decode::decode_text(
+      &contents,
+      password,
+      alg,
+      num_bits,
+      n_depth,
+      adaptive_model.as_ref(),
+      synonyms.as_ref(),
+      &policy,
+      find,
+    ) {
+      Ok(decoded) => decoded,
+      Err(_) => {
+        num_failed += 1;
+        continue;
+      },
+    };
+    // with --find, a password fails when no word of the text starts
+    // a payload header for it.
+
+    let (payload, score, reasons) = match utils::unframe_payload(&decoded, password) {
+      Ok((kind, payload)) => {
+        num_headers += 1;
+        let (_, mut reasons) = score_payload(&payload);
+        reasons.insert(0, match kind {
+          utils::PayloadKind::Data => "payload header matches".to_string(),
+          utils::PayloadKind::Share => "header of a --split share matches".to_string(),
+          utils::PayloadKind::Archive => "header of an archive matches".to_string(),
+        });
+        (payload, 100.0, reasons)
+      },
+      Err(_) => {
+        let (score, reasons) = score_payload(&decoded);
+        (decoded, score, reasons)
+      },
+    };
+    // the marker in the header is made from the password, so a header
+    // that matches all but proves the password is right, and only the
+    // payload without the filler is scored. texts encoded before
+    // payloads had a header are scored by how all their bytes look.
+
+    candidates.push(Candidate {
+      password: password.clone(),
+      payload,
+      score,
+      reasons,
+    });
+  }
+
+  candidates.sort_by(|a, b| b.score.partial_cmp(&a.score).unwrap());
+
+  // print summary
+  println!("\ntried {} passwords from {}", passwords.len(), wordlist);
+  if num_failed > 0 && find {
+    println!("{} passwords did not find a payload header anywhere in the text", num_failed);
+  } else if num_failed > 0 {
+    println!("{} passwords failed to decode", num_failed);
+  }
+  println!("{} passwords decode to a valid payload header", num_headers);
+  println!("\nmost plausible passwords (score 0 to 100):");
+  for (rank, candidate) in candidates.iter().take(num_top).enumerate() {
+    println!(
+      "  {:>3}. {:>5.1}  {:?}  ({} bytes, {})",
+      rank + 1,
+      candidate.score,
+      candidate.password,
+      candidate.payload.len(),
+      candidate.reasons.join(", "),
+    );
+    print_preview(&candidate.payload);
+  }
+
+  if let Some(best) = candidates.first() {
+    println!("\nIf the password you used is near the top of this list, anyone with");
+    println!("the same wordlist can read your data. Use a long, random password.");
+
+    if let Some(output) = matches.value_of("output") {
+      fs::write(output, &best.payload).map_err(|_| format!("Failed to write file: '{}'", output))?;
+      println!("\nwrote the payload for {:?} to {}", best.password, output);
+    }
+  }
+  println!();
+
+  Ok(())
+}
@@ -0,0 +1,59 @@
+use rand::Rng;
+
+#[derive(Clone, Copy)]
+pub struct Chat {
+  pub min_words: usize,
+  pub max_words: usize,
+  pub words_left: usize,
+}
+
+pub fn parse_message_words(range_str: &str) -> Result<Chat, String> {
+  let error = || format!("--message-words must look like min-max, ie: 3-12, not '{}'", range_str);
+  let mut parts = range_str.splitn(2, '-');
+  let min_words: usize = parts.next().and_then(|min| min.parse().ok()).ok_or_else(error)?;
+  let max_words: usize = parts.next().and_then(|max| max.parse().ok()).ok_or_else(error)?;
+
+  if min_words == 0 || min_words > max_words {
+    return Err(format!("--message-words needs 1 <= min <= max, you provided {}", range_str));
+  }
+
+  let mut chat = Chat { min_words, max_words, words_left: 0 };
+  start_message(&mut chat);
+  Ok(chat)
+}
+
+pub fn start_message(chat: &mut Chat) {
+  let mut rng = rand::thread_rng();
+  chat.words_left = rng.gen_range(chat.min_words, chat.max_words + 1);
+  // the length of a message does not carry any data, so it
+  // does not need the seeded rng, and the decoder ignores it.
+}
+
+pub fn push_separator<'a>(
+  chat: &mut Option<Chat>,
+  text_data: &mut String,
+  current_words: &mut Vec<&'a str>,
+  initial_words: &[&'a str],
+) {
+  let chat = match chat {
+    Some(chat) => chat,
+    None => {
+      text_data.push(' ');
+      return;
+    },
+  };
+
+  chat.words_left -= 1;
+  if chat.words_left > 0 {
+    text_data.push(' ');
+    return;
+  }
+
+  text_data.push('\n');
+  *current_words = initial_words.to_vec();
+  start_message(chat);
+  // every message starts over from the same context as the first one,
+  // so it reads like the start of something new instead of the middle
+  // of the last message. the decoders split on any whitespace, so the
+  // messages can be decoded one per line or all pasted together.
+}
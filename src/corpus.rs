@@ -0,0 +1,544 @@
+use std::collections::HashSet;
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use clap::ArgMatches;
+
+use super::tokenizer;
+use super::utils;
+
+const CORPUS_EXTENSIONS: [&str; 7] = ["txt", "text", "md", "html", "htm", "xhtml", "epub"];
+const HTML_SKIP_TAGS: [&str; 4] = ["script", "style", "head", "nav"];
+const HTML_BLOCK_TAGS: [&str; 16] = [
+  "p", "br", "div", "li", "tr", "td", "blockquote", "pre", "section",
+  "h1", "h2", "h3", "h4", "h5", "h6", "hr",
+];
+const HTML_ENTITIES: [(&str, &str); 16] = [
+  ("amp", "&"), ("lt", "<"), ("gt", ">"), ("quot", "\""), ("apos", "'"),
+  ("nbsp", " "), ("mdash", "--"), ("ndash", "-"), ("hellip", "..."),
+  ("lsquo", "'"), ("rsquo", "'"), ("ldquo", "\""), ("rdquo", "\""),
+  ("copy", "(c)"), ("shy", ""), ("emsp", " "),
+];
+const MARKUP_KEYWORDS: [&str; 6] = ["illustration", "footnote", "sidenote", "pg ", "transcriber", "blank page"];
+const GUTENBERG_STARTS: [&str; 2] = ["*** START OF", "***START OF"];
+const GUTENBERG_ENDS: [&str; 4] = ["*** END OF", "***END OF", "END OF THE PROJECT GUTENBERG", "END OF PROJECT GUTENBERG"];
+
+pub struct CorpusFile {
+  pub name: String,
+  pub text: String,
+  pub stripped_gutenberg: bool,
+}
+
+pub struct CorpusPart {
+  pub source: String,
+  pub start: usize,
+  pub end: usize,
+  pub weight: f64,
+}
+
+pub struct Corpus {
+  pub text: String,
+  pub parts: Vec<CorpusPart>,
+}
+
+pub fn is_glob(pattern: &str) -> bool {
+  pattern.contains('*') || pattern.contains('?') || pattern.contains('[')
+}
+
+pub fn has_corpus_extension(path: &Path) -> bool {
+  match path.extension() {
+    Some(extension) => CORPUS_EXTENSIONS.contains(&extension.to_string_lossy().to_lowercase().as_str()),
+    None => false,
+  }
+}
+
+pub fn add_directory(path: &Path, paths: &mut Vec<PathBuf>) -> Result<(), String> {
+  let mut children = vec![];
+  let dir = fs::read_dir(path).map_err(|_| format!("Failed to read directory: '{}'", path.display()))?;
+  for child in dir {
+    let child = child.map_err(|_| format!("Failed to read directory: '{}'", path.display()))?;
+    children.push(child.path());
+  }
+  children.sort();
+
+  for child in children {
+    if child.is_dir() {
+      add_directory(&child, paths)?;
+    } else if has_corpus_extension(&child) {
+      paths.push(child);
+    }
+  }
+  // only files that look like text, html or epub are read from a directory,
+  // so a folder of books can also have covers and notes in it.
+
+  Ok(())
+}
+
+pub fn expand_sources(sources: &[&str]) -> Result<Vec<PathBuf>, String> {
+  let mut paths = vec![];
+  for source in sources {
+    let path = Path::new(source);
+    if path.is_dir() {
+      add_directory(path, &mut paths)?;
+    } else if !path.exists() && is_glob(source) {
+      let matched = glob::glob(source).map_err(|e| format!("Invalid pattern '{}': {}", source, e))?;
+      let mut matched: Vec<PathBuf> = matched.filter_map(Result::ok).filter(|p| p.is_file()).collect();
+      if matched.is_empty() {
+        return Err(format!("No files match '{}'", source));
+      }
+      matched.sort();
+      paths.extend(matched);
+    } else {
+      paths.push(path.to_path_buf());
+    }
+  }
+  // everything is sorted, because the decoder of the adaptive
+  // algorithms has to build exactly the same model as the encoder.
+
+  Ok(paths)
+}
+
+pub fn parse_weighted_source(source: &str) -> Result<(&str, Option<f64>), String> {
+  if Path::new(source).exists() {
+    return Ok((source, None));
+  }
+
+  match source.rsplit_once(':') {
+    Some((path, weight_str)) => match weight_str.parse::<f64>() {
+      Ok(weight) if weight > 0.0 && weight.is_finite() => Ok((path, Some(weight))),
+      Ok(_) => Err(format!("The weight of '{}' must be a positive number, not '{}'", path, weight_str)),
+      Err(_) => Ok((source, None)),
+    },
+    None => Ok((source, None)),
+  }
+  // news.txt:0.7 is news.txt with a weight of 0.7, unless a file
+  // with that exact name exists.
+}
+
+pub fn decode_entities(text: &str) -> String {
+  let mut out = String::with_capacity(text.len());
+  let mut rest = text;
+  while let Some(start) = rest.find('&') {
+    out.push_str(&rest[..start]);
+    rest = &rest[start..];
+    let end = match rest[..rest.len().min(12)].find(';') {
+      Some(end) => end,
+      None => {
+        out.push('&');
+        rest = &rest[1..];
+        continue;
+      },
+    };
+
+    let name = &rest[1..end];
+    let decoded = if let Some(number) = name.strip_prefix('#') {
+      let code = if let Some(hex) = number.strip_prefix('x').or_else(|| number.strip_prefix('X')) {
+        u32::from_str_radix(hex, 16).ok()
+      } else {
+        number.parse::<u32>().ok()
+      };
+      code.and_then(std::char::from_u32).map(|c| c.to_string())
+    } else {
+      HTML_ENTITIES.iter().find(|(entity, _)| *entity == name).map(|(_, text)| text.to_string())
+    };
+
+    match decoded {
+      Some(decoded) => {
+        out.push_str(&decoded);
+        rest = &rest[end + 1..];
+      },
+      None => {
+        out.push('&');
+        rest = &rest[1..];
+      },
+    }
+  }
+  out.push_str(rest);
+
+  out
+}
+
+pub fn get_tag_name(tag: &str) -> String {
+  tag.trim_start_matches('/')
+    .chars()
+    .take_while(|c| c.is_ascii_alphanumeric())
+    .collect::<String>()
+    .to_lowercase()
+}
+
+pub fn html_to_text(html: &str) -> String {
+  let mut text = String::new();
+  let mut rest = html;
+  let mut skip_until: Option<String> = None;
+  while let Some(start) = rest.find('<') {
+    if skip_until.is_none() {
+      text.push_str(&decode_entities(&rest[..start]));
+    }
+    rest = &rest[start..];
+
+    if rest.starts_with("<!--") {
+      rest = match rest.find("-->") {
+        Some(end) => &rest[end + 3..],
+        None => "",
+      };
+      continue;
+    }
+
+    let end = match rest.find('>') {
+      Some(end) => end,
+      None => break,
+    };
+    let tag = &rest[1..end];
+    rest = &rest[end + 1..];
+
+    let name = get_tag_name(tag);
+    let is_closing = tag.starts_with('/');
+    match &skip_until {
+      Some(skip_name) => {
+        if is_closing && name == *skip_name {
+          skip_until = None;
+        }
+      },
+      None => {
+        if !is_closing && !tag.ends_with('/') && HTML_SKIP_TAGS.contains(&name.as_str()) {
+          skip_until = Some(name);
+        } else if HTML_BLOCK_TAGS.contains(&name.as_str()) {
+          text.push('\n');
+        }
+      },
+    }
+  }
+  if skip_until.is_none() {
+    text.push_str(&decode_entities(rest));
+  }
+  // not a real html parser, but enough to get the text out of a
+  // book: scripts, styles and the head are dropped, and block tags
+  // become line breaks so words from different paragraphs do not merge.
+
+  text
+}
+
+pub fn get_attribute<'a>(tag: &'a str, name: &str) -> Option<&'a str> {
+  for quote in ['"', '\''].iter() {
+    let pattern = format!("{}={}", name, quote);
+    let mut search_from = 0;
+    while let Some(index) = tag[search_from..].find(&pattern) {
+      let start = search_from + index;
+      let preceded_by_space = start == 0 || tag[..start].ends_with(char::is_whitespace);
+      let value_start = start + pattern.len();
+      if preceded_by_space {
+        if let Some(value_len) = tag[value_start..].find(*quote) {
+          return Some(&tag[value_start..value_start + value_len]);
+        }
+      }
+      search_from = value_start;
+    }
+  }
+
+  None
+}
+
+pub fn get_tags<'a>(xml: &'a str, tag_name: &str) -> Vec<&'a str> {
+  let mut tags = vec![];
+  let mut rest = xml;
+  while let Some(start) = rest.find('<') {
+    rest = &rest[start + 1..];
+    let end = match rest.find('>') {
+      Some(end) => end,
+      None => break,
+    };
+    let tag = &rest[..end];
+    let name = tag.split_whitespace().next().unwrap_or("");
+    if name == tag_name || name.ends_with(&format!(":{}", tag_name)) {
+      tags.push(tag);
+    }
+    rest = &rest[end + 1..];
+  }
+
+  tags
+}
+
+pub fn read_zip_entry(archive: &mut zip::ZipArchive<fs::File>, name: &str) -> Option<String> {
+  let mut entry = archive.by_name(name).ok()?;
+  let mut contents = vec![];
+  entry.read_to_end(&mut contents).ok()?;
+  Some(String::from_utf8_lossy(&contents).into_owned())
+}
+
+pub fn get_epub_spine(archive: &mut zip::ZipArchive<fs::File>) -> Option<Vec<String>> {
+  let container = read_zip_entry(archive, "META-INF/container.xml")?;
+  let opf_path = get_tags(&container, "rootfile").iter().find_map(|tag| get_attribute(tag, "full-path"))?.to_string();
+  let opf = read_zip_entry(archive, &opf_path)?;
+  let opf_dir = match opf_path.rfind('/') {
+    Some(index) => &opf_path[..index + 1],
+    None => "",
+  };
+
+  let items: Vec<(&str, &str)> = get_tags(&opf, "item").iter()
+    .filter_map(|tag| Some((get_attribute(tag, "id")?, get_attribute(tag, "href")?)))
+    .collect();
+  let spine: Vec<String> = get_tags(&opf, "itemref").iter()
+    .filter_map(|tag| get_attribute(tag, "idref"))
+    .filter_map(|idref| items.iter().find(|(id, _)| *id == idref))
+    .map(|(_, href)| format!("{}{}", opf_dir, href))
+    .collect();
+  // the spine lists the chapters in reading order, by the ids
+  // of the files listed in the manifest.
+
+  if spine.is_empty() { None } else { Some(spine) }
+}
+
+pub fn epub_to_text(file_name: &str) -> Result<String, String> {
+  let file = fs::File::open(file_name).map_err(|_| format!("Failed to read file: '{}'", file_name))?;
+  let mut archive = zip::ZipArchive::new(file).map_err(|_| format!("'{}' is not a valid epub file", file_name))?;
+
+  let chapters = match get_epub_spine(&mut archive) {
+    Some(spine) => spine,
+    None => {
+      let mut names: Vec<String> = archive.file_names()
+        .filter(|name| has_corpus_extension(Path::new(name)) && !name.ends_with(".epub"))
+        .map(String::from)
+        .collect();
+      names.sort();
+      names
+    },
+  };
+  // if the book has no readable spine, fall back to every
+  // html file in it, in the order of their names.
+
+  let mut text = String::new();
+  for chapter in chapters {
+    if let Some(html) = read_zip_entry(&mut archive, &chapter) {
+      text.push_str(&html_to_text(&html));
+      text.push('\n');
+    }
+  }
+
+  Ok(text)
+}
+
+pub fn strip_gutenberg(text: &str) -> Option<String> {
+  let lines: Vec<&str> = text.lines().collect();
+  let is_marker = |line: &str, markers: &[&str]| {
+    let upper = line.trim().to_uppercase();
+    markers.iter().any(|marker| upper.starts_with(marker)) && upper.contains("GUTENBERG")
+  };
+
+  let start = lines.iter().position(|line| is_marker(line, &GUTENBERG_STARTS))?;
+  let end = lines.iter().skip(start + 1).position(|line| is_marker(line, &GUTENBERG_ENDS))
+    .map(|index| index + start + 1)
+    .unwrap_or(lines.len());
+  // the license header ends with a line like
+  // *** START OF THE PROJECT GUTENBERG EBOOK VARNEY THE VAMPIRE ***
+  // and the license at the end starts with a matching END OF line.
+
+  let body = lines[start + 1..end].join("\n");
+  Some(strip_italics(&body))
+}
+
+pub fn strip_italics(text: &str) -> String {
+  let chars: Vec<char> = text.chars().collect();
+  let mut out = String::with_capacity(text.len());
+  for (i, c) in chars.iter().enumerate() {
+    if *c == '_' {
+      let previous = if i > 0 { Some(chars[i - 1]) } else { None };
+      let next = chars.get(i + 1).copied();
+      let is_leading = previous.map_or(true, |p| !p.is_alphanumeric() && p != '_') && next.is_some_and(char::is_alphanumeric);
+      let is_trailing = previous.is_some_and(|p| !p.is_whitespace() && p != '_') && next.map_or(true, |n| !n.is_alphanumeric() && n != '_');
+      if is_leading || is_trailing {
+        continue;
+      }
+    }
+    out.push(*c);
+  }
+  // gutenberg marks italics with underscores, ie: _very_ or _Mr. Smith._
+  // only the ones at the start and end of a word are removed, so
+  // names like snake_case and blanks like ____ stay as they are.
+
+  out
+}
+
+pub fn strip_markup(text: &str) -> String {
+  let mut out = String::with_capacity(text.len());
+  let mut rest = text;
+  while let Some(start) = rest.find('[') {
+    out.push_str(&rest[..start]);
+    rest = &rest[start..];
+
+    let end = rest.find(']');
+    let inner = end.map(|end| rest[1..end].trim_start().to_lowercase());
+    match (end, inner) {
+      (Some(end), Some(inner)) if MARKUP_KEYWORDS.iter().any(|keyword| inner.starts_with(keyword)) => {
+        rest = &rest[end + 1..];
+      },
+      _ => {
+        out.push('[');
+        rest = &rest[1..];
+      },
+    }
+  }
+  out.push_str(rest);
+  // things like [Illustration: the vampire] or [Pg 12] are not part of
+  // the story, and would otherwise show up in the stegotext.
+
+  out
+}
+
+pub fn load_corpus_file(path: &Path) -> Result<CorpusFile, String> {
+  let name = path.to_string_lossy().into_owned();
+  let extension = path.extension().map(|e| e.to_string_lossy().to_lowercase()).unwrap_or_default();
+  let text = match extension.as_str() {
+    "epub" => epub_to_text(&name)?,
+    "html" | "htm" | "xhtml" => html_to_text(&String::from_utf8_lossy(&utils::get_file_contents(&name)?)),
+    _ => utils::get_file_contents_as_string(&name)?,
+  };
+
+  let (text, stripped_gutenberg) = match strip_gutenberg(&text) {
+    Some(body) => (body, true),
+    None => (text, false),
+  };
+
+  Ok(CorpusFile {
+    name,
+    text: strip_markup(&text),
+    stripped_gutenberg,
+  })
+}
+
+pub fn load_sources(sources: &[&str], policy: &tokenizer::TokenizerPolicy) -> Result<Corpus, String> {
+  let mut weighted_sources = vec![];
+  for source in sources {
+    weighted_sources.push(parse_weighted_source(source)?);
+  }
+
+  let groups: Vec<(Vec<&str>, f64)> = if weighted_sources.iter().any(|(_, weight)| weight.is_some()) {
+    weighted_sources.iter().map(|(source, weight)| (vec![*source], weight.unwrap_or(1.0))).collect()
+  } else {
+    vec![(weighted_sources.iter().map(|(source, _)| *source).collect(), 1.0)]
+  };
+  // without weights every file is counted together as one mimic text.
+  // with weights, every --words is its own part of the model, and
+  // a --words without a weight gets a weight of 1.
+
+  let mut files = vec![];
+  let mut group_files = vec![];
+  for (group, _) in &groups {
+    let start = files.len();
+    for path in expand_sources(group)? {
+      files.push(load_corpus_file(&path)?);
+    }
+    group_files.push(start..files.len());
+  }
+
+  let mut texts = vec![];
+  let mut unique_words = HashSet::new();
+  let mut total_words = 0;
+  for file in &files {
+    let text = tokenizer::tokenize(&file.text, policy);
+    let words: Vec<&str> = text.split_whitespace().filter(|w| !utils::is_punctuation(w)).collect();
+    unique_words.extend(words.iter().map(|w| w.to_string()));
+    total_words += words.len();
+    if files.len() > 1 || file.stripped_gutenberg {
+      println!(
+        "  {}: {} words{}",
+        file.name,
+        words.len(),
+        if file.stripped_gutenberg { " (removed project gutenberg header and footer)" } else { "" },
+      );
+    }
+    texts.push(text);
+  }
+  println!("mimic text: {} words, {} different words, from {} files", total_words, unique_words.len(), files.len());
+  // every file is tokenized here, once, with the same policy the
+  // decoder uses, so the encoders can split the text on whitespace.
+
+  let total_weight: f64 = groups.iter().map(|(_, weight)| weight).sum();
+  let mut text = String::new();
+  let mut parts = vec![];
+  for ((group, weight), file_range) in groups.iter().zip(group_files) {
+    if !text.is_empty() {
+      text.push(' ');
+    }
+    let start = text.len();
+    text.push_str(&texts[file_range].join(" "));
+    parts.push(CorpusPart {
+      source: group.join(", "),
+      start,
+      end: text.len(),
+      weight: weight / total_weight,
+    });
+  }
+
+  if parts.len() > 1 {
+    println!("mixing {} mimic texts:", parts.len());
+    for part in &parts {
+      println!("  {}: {:.0}%", part.source, 100.0 * part.weight);
+    }
+  }
+
+  Ok(Corpus { text, parts })
+}
+
+pub fn load_corpus(matches: &ArgMatches) -> Result<Corpus, String> {
+  let policy = tokenizer::get_policy(matches)?;
+  match matches.values_of("words") {
+    Some(sources) => load_sources(&sources.collect::<Vec<&str>>(), &policy),
+    None => Err("failed to get value of words".into()),
+  }
+}
+
+pub fn get_optional_corpus(matches: &ArgMatches) -> Result<Option<Corpus>, String> {
+  let policy = tokenizer::get_policy(matches)?;
+  match matches.values_of("words") {
+    Some(sources) => Ok(Some(load_sources(&sources.collect::<Vec<&str>>(), &policy)?)),
+    None => Ok(None),
+  }
+}
+
+pub fn hold_out(corpus: &Corpus, fraction: f64) -> (Corpus, String) {
+  let mut text = String::new();
+  let mut held_out = vec![];
+  let mut parts = vec![];
+  for part in &corpus.parts {
+    let words: Vec<&str> = corpus.text[part.start..part.end].split_whitespace().collect();
+    let split = words.len() - (words.len() as f64 * fraction) as usize;
+    if !text.is_empty() {
+      text.push(' ');
+    }
+    let start = text.len();
+    text.push_str(&words[..split].join(" "));
+    held_out.push(words[split..].join(" "));
+    parts.push(CorpusPart {
+      source: part.source.clone(),
+      start,
+      end: text.len(),
+      weight: part.weight,
+    });
+  }
+  // the end of every part is kept out of the model, so there is
+  // text from each mimic text that the model has never seen.
+
+  (Corpus { text, parts }, held_out.join(" "))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn strip_italics_only_strips_word_boundaries() {
+    assert_eq!(strip_italics("it was _very_ late"), "it was very late");
+    assert_eq!(strip_italics("_Mr. Smith._ said so"), "Mr. Smith. said so");
+    assert_eq!(strip_italics("(_see_ below), _1820_"), "(see below), 1820");
+    assert_eq!(strip_italics("call snake_case here"), "call snake_case here");
+    assert_eq!(strip_italics("a blank ____ and __init__"), "a blank ____ and __init__");
+  }
+
+  #[test]
+  fn strip_gutenberg_keeps_the_body() {
+    let text = "license\n*** START OF THE PROJECT GUTENBERG EBOOK TEST ***\nthe _body_ of my_book\n*** END OF THE PROJECT GUTENBERG EBOOK TEST ***\nlicense";
+    assert_eq!(strip_gutenberg(text).unwrap(), "the body of my_book");
+    assert!(strip_gutenberg("no markers here").is_none());
+  }
+}
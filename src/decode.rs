@@ -1,20 +1,58 @@
 use std::fs;
+use std::collections::HashMap;
 
 use clap::ArgMatches;
+use rand::prelude::StdRng;
 use bitstream_io::{BigEndian, BitWriter};
-use rpassword;
 
 use super::utils;
+use super::adapter;
+use super::archive;
+use super::corpus;
+use super::encode;
+use super::format;
+use super::synonym;
+use super::invisible;
+use super::pubkey;
+use super::secret;
+use super::split;
+use super::template;
+use super::tokenizer;
+
+const HEADER_WINDOW: usize = 64;
+
+pub struct AdaptiveModel<'a> {
+  pub gram: HashMap<Vec<&'a str>, usize>,
+  pub continuations: HashMap<Vec<&'a str>, usize>,
+}
+
+pub fn get_adaptive_model<'a>(
+  word_file_data: Option<&'a corpus::Corpus>,
+  alg: utils::Algorithm,
+  n_depth: usize,
+) -> Option<AdaptiveModel<'a>> {
+  match (alg, word_file_data) {
+    (utils::Algorithm::Shuffle(utils::ValueMode::AdaptiveCharValue(_)), Some(data)) |
+    (utils::Algorithm::NoShuffle(utils::ValueMode::AdaptiveCharValue(_)), Some(data)) => {
+      let (gram, _, _) = encode::generate_corpus_ngrams(data, n_depth);
+      let continuations = encode::generate_continuation_counts(&gram);
+      Some(AdaptiveModel { gram, continuations })
+    },
+    _ => None,
+  }
+  // the number of bits each word carries depends on the words before it,
+  // so the decoder has to rebuild the same n-gram model that the encoder used.
+  // it is built once and shared by every decode of the same text.
+}
 
 
 pub fn decode_char_bit_mode(
-  file: &str,
-  output: &str,
+  contents: &str,
   seed_str: &str,
   num_bits: usize,
   use_shuffle: bool,
   value_mode: utils::ValueMode,
-) -> Result<(), String> {
+) -> Result<Vec<u8>, String> {
   let mut rng = utils::create_rng_from_seed(seed_str);
 
   let mut bit_to_char_map = utils::make_bit_to_char_map(num_bits);
@@ -23,9 +61,8 @@ pub fn decode_char_bit_mode(
 
 
   let mut bitwriter = BitWriter::endian(Vec::new(), BigEndian);
-  let contents = utils::get_file_contents_as_string(file)?;
 
-  let encoded_words = contents.split(' ').collect::<Vec<&str>>();
+  let encoded_words = contents.split_whitespace().collect::<Vec<&str>>();
 
   let mut total_bits = ((encoded_words.len() * num_bits) / 8) * 8;
 
@@ -47,40 +84,50 @@ pub fn decode_char_bit_mode(
       total_bits as u32
     };
 
-    bitwriter.write(write_bits, value as u8).unwrap();
+    bitwriter.write(write_bits, (value & ((1 << write_bits) - 1)) as u8).unwrap();
+    // a text with words that were not encoded, ie: the words around a
+    // --template, can end on a value that does not fit the last bits.
     total_bits -= write_bits as usize;
   }
 
 
-  let out_vec = bitwriter.into_writer();
-  fs::write(output, out_vec).unwrap();
-
-
-  Ok(())
+  Ok(bitwriter.into_writer())
 }
 
 pub fn decode_char_value_mode(
-  file: &str,
-  output: &str,
+  contents: &str,
   seed_str: &str,
   num_bits: usize,
   use_shuffle: bool,
   value_mode: utils::ValueMode,
-) -> Result<(), String> {
+) -> Result<Vec<u8>, String> {
   let mut rng = utils::create_rng_from_seed(seed_str);
 
-  let mut char_to_value_map = utils::make_char_to_value_map(num_bits);
+  let mut char_to_value_map = utils::make_value_map_for_mode(&value_mode, num_bits);
   utils::shuffle_char_value_map(&mut rng, &mut char_to_value_map);
 
   let mut bitwriter = BitWriter::endian(Vec::new(), BigEndian);
-  let contents = utils::get_file_contents_as_string(file)?;
 
-  let encoded_words = contents.split(' ').collect::<Vec<&str>>();
+  let mut encoded_words = contents.split_whitespace().collect::<Vec<&str>>();
+  if let utils::ValueMode::SentenceAcrostic(_) = value_mode {
+    encoded_words = utils::get_sentence_starts(&encoded_words);
+    // only the first word of every sentence carries a value
+  }
+  if let utils::ValueMode::WordAcrostic(_) = value_mode {
+    let mut position_rng = utils::create_position_rng(seed_str);
+    encoded_words.retain(|_| utils::is_data_position(&mut position_rng));
+    // the other words were picked freely
+  }
 
   let mut total_bits = ((encoded_words.len() * num_bits) / 8) * 8;
+  let hash_values = utils::get_word_hash_values(&encoded_words, &value_mode, seed_str.as_bytes(), use_shuffle);
 
-  for word in encoded_words {
-    let value = utils::get_value_from_chars(word, &char_to_value_map, &value_mode);
+  for (i, word) in encoded_words.iter().enumerate() {
+    let hash_index = if use_shuffle { Some(i) } else { None };
+    let value = match &hash_values {
+      Some(values) => values[word],
+      None => utils::get_word_value(word, &char_to_value_map, &value_mode, seed_str.as_bytes(), hash_index),
+    };
 
     if use_shuffle {
       utils::shuffle_char_value_map(&mut rng, &mut char_to_value_map);
@@ -92,42 +139,72 @@ pub fn decode_char_value_mode(
       total_bits as u32
     };
 
-    bitwriter.write(write_bits, value as u8).unwrap();
+    bitwriter.write(write_bits, (value & ((1 << write_bits) - 1)) as u8).unwrap();
     total_bits -= write_bits as usize;
   }
 
 
-  let out_vec = bitwriter.into_writer();
-  fs::write(output, out_vec).unwrap();
+  Ok(bitwriter.into_writer())
+}
 
+pub fn decode_adaptive_char_value_mode(
+  contents: &str,
+  seed_str: &str,
+  adaptive_model: &AdaptiveModel,
+  n_depth: usize,
+  max_bits: usize,
+  use_shuffle: bool,
+) -> Result<Vec<u8>, String> {
+  let mut rng = utils::create_rng_from_seed(seed_str);
 
-  Ok(())  
+  let mut char_to_value_map = utils::make_char_to_value_map(max_bits);
+  utils::shuffle_char_value_map(&mut rng, &mut char_to_value_map);
+
+  let current_words = encode::get_sorted_initial_words(&adaptive_model.gram, n_depth);
+  let words: Vec<&str> = contents.split_whitespace().collect();
+  Ok(decode_adaptive_words(&words, &mut rng, &mut char_to_value_map, &adaptive_model.continuations, current_words, n_depth, max_bits, use_shuffle))
 }
 
-pub fn decode(matches: &ArgMatches) -> Result<(), String> {
-  let file = utils::get_value(matches, "file")?;
-  let output = utils::get_value(matches, "output")?;
-  let password_str = utils::get_value(matches, "password")?;
-  let mut seed_str = utils::get_value(matches, "seed")?;
-  let alg_str = utils::get_value(matches, "algorithm")?;
-  let num_bits = utils::get_numerical_value(matches, "bits")?;
+#[allow(clippy::too_many_arguments)]
+pub fn decode_adaptive_words<'a>(
+  words: &[&'a str],
+  rng: &mut StdRng,
+  char_to_value_map: &mut HashMap<char, usize>,
+  continuations: &HashMap<Vec<&str>, usize>,
+  mut current_words: Vec<&'a str>,
+  n_depth: usize,
+  max_bits: usize,
+  use_shuffle: bool,
+) -> Vec<u8> {
+  let mut bitwriter = BitWriter::endian(Vec::new(), BigEndian);
 
-  if num_bits > 8 || num_bits < 1 {
-    return Err(format!("Bits must be between 1 and 8 inclusively, you provided {}", num_bits));
-  }
+  for word in words {
+    let num_bits = encode::get_adaptive_bits(continuations, &current_words, n_depth, max_bits);
+    let value_mode = utils::ValueMode::CharValueMap(num_bits);
+    let value = utils::get_value_from_chars(word, char_to_value_map, &value_mode);
 
-  let pass;
-  match password_str {
-    "true" => {
-      // get seed string interactively
-      pass = rpassword::prompt_password_stderr("Enter password that file was encoded with: ").unwrap();
-      seed_str = pass.as_str();
-    },
-    _ => (),
-  };
+    if use_shuffle {
+      utils::shuffle_char_value_map(rng, char_to_value_map);
+    }
 
-  let alg = utils::get_algorithm_from_string(alg_str, num_bits)?;
+    bitwriter.write(num_bits as u32, value as u8).unwrap();
+    current_words.push(word);
+  }
+  // the encoder pads the last value with zeros. any bits that
+  // do not make up a full byte are dropped by into_writer.
+
+  bitwriter.into_writer()
+}
 
+pub fn decode_to_bytes(
+  contents: &str,
+  seed_str: &str,
+  alg: utils::Algorithm,
+  num_bits: usize,
+  n_depth: usize,
+  adaptive_model: Option<&AdaptiveModel>,
+  synonyms: Option<&synonym::SynonymDictionary>,
+) -> Result<Vec<u8>, String> {
   let (use_shuffle, value_mode) = match alg {
     utils::Algorithm::Shuffle(mode) => {
       (true, mode)
@@ -135,30 +212,336 @@ pub fn decode(matches: &ArgMatches) -> Result<(), String> {
     utils::Algorithm::NoShuffle(mode) => {
       (false, mode)
     },
+    utils::Algorithm::Invisible(carrier) => {
+      return invisible::get_invisible_payload(
+        contents,
+        seed_str,
+        &carrier,
+      );
+    },
+    utils::Algorithm::Synonym => {
+      let dictionary = match synonyms {
+        Some(dictionary) => dictionary,
+        None => return Err("Must provide --synonyms when decoding with the synonym algorithm".into()),
+      };
+      return synonym::get_synonym_payload(
+        contents,
+        seed_str,
+        dictionary,
+      );
+    },
   };
 
   match value_mode {
     utils::ValueMode::CharBitMap => {
       decode_char_bit_mode(
-        file,
-        output,
-        seed_str, 
-        num_bits, 
+        contents,
+        seed_str,
+        num_bits,
         use_shuffle,
         value_mode
       )
     },
-    utils::ValueMode::CharValueMap(_) => {
+    utils::ValueMode::CharValueMap(_) |
+    utils::ValueMode::WordAcrostic(_) |
+    utils::ValueMode::SentenceAcrostic(_) |
+    utils::ValueMode::WordHash(_) => {
       decode_char_value_mode(
-        file,
-        output,
-        seed_str, 
-        num_bits, 
+        contents,
+        seed_str,
+        num_bits,
         use_shuffle,
         value_mode
       )
     },
+    utils::ValueMode::AdaptiveCharValue(max_bits) => {
+      let adaptive_model = match adaptive_model {
+        Some(adaptive_model) => adaptive_model,
+        None => return Err("Must provide --words when decoding with an adaptive algorithm".into()),
+      };
+      decode_adaptive_char_value_mode(
+        contents,
+        seed_str,
+        adaptive_model,
+        n_depth,
+        max_bits,
+        use_shuffle,
+      )
+    },
+  }
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn find_framed_region(
+  contents: &str,
+  seed_str: &str,
+  alg: utils::Algorithm,
+  num_bits: usize,
+  n_depth: usize,
+  adaptive_model: Option<&AdaptiveModel>,
+  policy: Option<&tokenizer::TokenizerPolicy>,
+  is_header: &dyn Fn(&[u8]) -> bool,
+) -> Result<Option<Vec<u8>>, String> {
+  let (use_shuffle, value_mode) = match alg {
+    utils::Algorithm::Shuffle(mode) => (true, mode),
+    utils::Algorithm::NoShuffle(mode) => (false, mode),
+    _ => return Ok(None),
+  };
+  let adaptive = match (value_mode, adaptive_model) {
+    (utils::ValueMode::AdaptiveCharValue(max_bits), Some(adaptive_model)) => Some((adaptive_model, max_bits)),
+    (utils::ValueMode::AdaptiveCharValue(_), None) => return Ok(None),
+    _ => None,
+  };
+
+  let words: Vec<&str> = contents.split_whitespace().collect();
+  let decode_region = |start: usize, end: usize| -> Result<Vec<u8>, String> {
+    match &adaptive {
+      Some((adaptive_model, max_bits)) => {
+        let mut rng = utils::create_rng_from_seed(seed_str);
+        let mut char_to_value_map = utils::make_char_to_value_map(*max_bits);
+        utils::shuffle_char_value_map(&mut rng, &mut char_to_value_map);
+        let context = match policy {
+          Some(policy) => template::get_context_words(&words[..start], policy),
+          None => vec![],
+        };
+        let current_words = if context.is_empty() {
+          encode::get_sorted_initial_words(&adaptive_model.gram, n_depth)
+        } else {
+          context.iter().map(String::as_str).collect()
+        };
+        Ok(decode_adaptive_words(&words[start..end], &mut rng, &mut char_to_value_map, &adaptive_model.continuations, current_words, n_depth, *max_bits, use_shuffle))
+      },
+      None => decode_to_bytes(&words[start..end].join(" "), seed_str, alg, num_bits, n_depth, None, None),
+    }
+  };
+  // the adaptive encoder went on from the template words before the
+  // stegotext, so the decoder has to use the same words as context.
+  // without a policy, the stegotext started on its own.
+
+  for start in 1..words.len() {
+    let mut end = (start + HEADER_WINDOW).min(words.len());
+    let mut header = decode_region(start, end)?;
+    while header.len() < utils::FRAME_HEADER_LEN && end < words.len() {
+      end = (start + (end - start) * 2).min(words.len());
+      header = decode_region(start, end)?;
+    }
+    // only decode enough words to see if there is a header here,
+    // skip words can make that more than a few.
+
+    if is_header(&header) {
+      return Ok(Some(decode_region(start, words.len())?));
+    }
+  }
+  // every word could be the first word of the stegotext, the one
+  // where the decoded bytes start with a frame header is it. the
+  // words after the stegotext decode to bytes after the payload,
+  // which are thrown away like the filler.
+
+  Ok(None)
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn decode_text(
+  contents: &str,
+  seed_str: &str,
+  alg: utils::Algorithm,
+  num_bits: usize,
+  n_depth: usize,
+  adaptive_model: Option<&AdaptiveModel>,
+  synonyms: Option<&synonym::SynonymDictionary>,
+  policy: &tokenizer::TokenizerPolicy,
+  find: bool,
+) -> Result<Vec<u8>, String> {
+  let out_vec = decode_to_bytes(contents, seed_str, alg, num_bits, n_depth, adaptive_model, synonyms)?;
+  if !find || utils::get_framed_length(&out_vec, seed_str).is_ok() {
+    return Ok(out_vec);
+  }
+
+  let is_header = |header: &[u8]| utils::get_framed_length(header, seed_str).is_ok();
+  match find_framed_region(contents, seed_str, alg, num_bits, n_depth, adaptive_model, Some(policy), &is_header)? {
+    Some(region) => Ok(region),
+    None => Err("Could not find a payload header anywhere in the text. Was the seed correct?".into()),
+  }
+  // a text encoded with --template has words before the stegotext,
+  // so it does not start with the header. searching for it decodes
+  // the text once for every word, so it is only done with --find.
+}
+
+pub fn get_payload(out_vec: &[u8], seed_str: &str) -> Result<(utils::PayloadKind, Vec<u8>), String> {
+  utils::unframe_payload(out_vec, seed_str).map_err(|e| format!(
+    "{} Texts encoded with --template need --find",
+    e,
+  ))
+}
+
+pub fn get_optional_synonyms(matches: &ArgMatches) -> Result<Option<synonym::SynonymDictionary>, String> {
+  match matches.value_of("synonyms") {
+    Some(file_name) => Ok(Some(synonym::load_synonym_sets(file_name)?)),
+    None => Ok(None),
+  }
+}
+
+pub fn write_payload(matches: &ArgMatches, kind: utils::PayloadKind, out_vec: &[u8]) -> Result<(), String> {
+  if let Some(output_dir) = matches.value_of("output_dir") {
+    if kind != utils::PayloadKind::Archive {
+      return Err("The payload is not an archive. Was it encoded with more than one --file, or a directory?".into());
+    }
+    let entries = archive::from_bytes(out_vec)?;
+    archive::extract(&entries, output_dir, matches.is_present("force"))?;
+
+    // print summary
+    println!("\nextracted {} files to {}:", entries.len(), output_dir);
+    archive::print_entries(&entries);
+    println!();
+    return Ok(());
+  }
+
+  if kind == utils::PayloadKind::Archive {
+    let entries = archive::from_bytes(out_vec)?;
+    return Err(format!(
+      "The payload is an archive of {} files, use --output-dir to extract them",
+      entries.len(),
+    ));
+  }
+  // the header of the payload says whether it is an archive, so a
+  // single file that happens to start like one is still written.
+
+  let output = utils::get_value(matches, "output")?;
+  fs::write(output, out_vec).unwrap();
+
+  Ok(())
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn decode_shares(
+  files: &[&str],
+  seed_str: &str,
+  alg: utils::Algorithm,
+  num_bits: usize,
+  n_depth: usize,
+  adaptive_model: Option<&AdaptiveModel>,
+  policy: &tokenizer::TokenizerPolicy,
+  input_format: format::InputFormat,
+  find: bool,
+) -> Result<(utils::PayloadKind, Vec<u8>), String> {
+  let mut shares = vec![];
+  for file in files {
+    let contents = adapter::read_text(&utils::get_file_contents_as_string(file)?, input_format)?;
+    let share = decode_text(&contents, seed_str, alg, num_bits, n_depth, adaptive_model, None, policy, find)
+      .and_then(|data| get_payload(&data, seed_str))
+      .and_then(|(kind, data)| match kind {
+        utils::PayloadKind::Share => split::share_from_bytes(&data),
+        utils::PayloadKind::Data | utils::PayloadKind::Archive => Err("it holds a whole payload, decode it on its own".into()),
+      })
+      .map_err(|e| format!("'{}' is not a share that can be decoded with this seed: {}", file, e))?;
+    println!("'{}' is share {} of {}, {} needed", file, share.x, share.num_shares, share.threshold);
+    shares.push(share);
+  }
+  // the shares can be given in any order, every share knows its own
+  // number and how many are needed.
+
+  split::combine_payload(&shares)
+}
+
+pub fn decode(matches: &ArgMatches) -> Result<(), String> {
+  let file = utils::get_value(matches, "file")?;
+  let alg_str = utils::get_value(matches, "algorithm")?;
+  let num_bits = utils::get_numerical_value(matches, "bits")?;
+  let n_depth = utils::get_numerical_value(matches, "n")?;
+  let files: Vec<&str> = matches.values_of("file").map(|values| values.collect()).unwrap_or_default();
+
+  if !(1..=8).contains(&num_bits) {
+    return Err(format!("Bits must be between 1 and 8 inclusively, you provided {}", num_bits));
+  }
+
+  let alg = utils::get_algorithm_from_string(alg_str, num_bits)?;
+  let word_file_data = corpus::get_optional_corpus(matches)?;
+  let adaptive_model = get_adaptive_model(word_file_data.as_ref(), alg, n_depth);
+  let policy = tokenizer::get_policy(matches)?;
+  let input_format = adapter::get_input_format(matches, alg)?;
+  let find = matches.is_present("find");
+  let raw = matches.is_present("raw");
+
+  if files.len() > 1 {
+    if matches.is_present("key") {
+      return Err("--key can only decode one file at a time".into());
+    }
+    let secret = secret::get_secret(matches, "Enter password that the files were encoded with: ", false)?;
+    let (kind, out_vec) = decode_shares(&files, secret.as_str(), alg, num_bits, n_depth, adaptive_model.as_ref(), &policy, input_format, find)?;
+    return write_payload(matches, kind, &out_vec);
+  }
+  // more than one file means the payload was split with encode --split
+
+  let contents = adapter::read_text(&utils::get_file_contents_as_string(file)?, input_format)?;
+
+  if let Some(key_file) = matches.value_of("key") {
+    if secret::get_num_secret_sources(matches) > 0 {
+      return Err("--key can not be combined with a seed or password, the seed comes from the key exchange".into());
+    }
+    let (kind, out_vec) = pubkey::decode_with_private_key(
+      &contents,
+      key_file,
+      alg,
+      num_bits,
+      n_depth,
+      adaptive_model.as_ref(),
+    )?;
+    return write_payload(matches, kind, &out_vec);
+  }
+
+  let secret = secret::get_secret(matches, "Enter password that file was encoded with: ", false)?;
+  let seed_str = secret.as_str();
+  let synonyms = get_optional_synonyms(matches)?;
+
+  let out_vec = match decode_text(
+    &contents,
+    seed_str,
+    alg,
+    num_bits,
+    n_depth,
+    adaptive_model.as_ref(),
+    synonyms.as_ref(),
+    &policy,
+    find,
+  ) {
+    Ok(out_vec) => out_vec,
+    Err(e) => {
+      if let utils::Algorithm::Invisible(carrier) = alg {
+        invisible::print_decode_failure(&contents, &carrier);
+      }
+      return Err(e);
+    },
+  };
+  if raw {
+    return write_payload(matches, utils::PayloadKind::Data, &out_vec);
   }
+  if !find && utils::get_framed_length(&out_vec, seed_str).is_err() {
+    println!("\nWarning: the text does not start with a payload header for this seed.");
+    println!("Writing every decoded byte as it is, like a text encoded before payloads had a header.");
+    println!("If this is not such a text, the seed is wrong, or the text needs --find or --input-format.\n");
+    return write_payload(matches, utils::PayloadKind::Data, &out_vec);
+  }
+  // texts encoded before payloads had a header are written as they
+  // are decoded, so old scripts keep working. --raw does that even
+  // when the decoded bytes happen to start like a header.
+
+  let (kind, out_vec) = match get_payload(&out_vec, seed_str)? {
+    (utils::PayloadKind::Share, payload) => {
+      let share = split::share_from_bytes(&payload)?;
+      return Err(format!(
+        "'{}' is share {} of {}, give at least {} shares with one --file each to rebuild the payload",
+        file,
+        share.x,
+        share.num_shares,
+        share.threshold,
+      ));
+    },
+    (kind, payload) => (kind, payload),
+  };
+  // the header says how long the payload is and whether it is a
+  // share, the filler after it is thrown away.
+
+  write_payload(matches, kind, &out_vec)
 
 
   // let mut rng = utils::create_rng_from_seed(seed_str);
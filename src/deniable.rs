@@ -0,0 +1,161 @@
+use std::collections::HashMap;
+
+use rand::prelude::StdRng;
+
+use super::chat;
+use super::encode;
+use super::utils;
+
+pub struct Layer<'a> {
+  seed_str: &'a str,
+  values: Vec<u8>,
+  positions: Vec<bool>,
+  index: usize,
+  rng: StdRng,
+  char_to_value_map: HashMap<char, usize>,
+}
+
+pub fn pad_payload(payload: &[u8], seed_str: &str, length: usize, num_words: usize) -> Vec<u8> {
+  let padded = encode::add_filler(payload, seed_str, length - payload.len() + num_words);
+  // the shorter payload is brought to the length of the longer one
+  // with the same keyed filler a normal encode puts after its payload,
+  // so with either seed the text decodes like one encoded with --min-words.
+
+  padded
+}
+
+pub fn make_layer<'a>(
+  payload: &[u8],
+  seed_str: &'a str,
+  positions: Vec<bool>,
+  value_mode: &utils::ValueMode,
+  num_bits: usize,
+  use_shuffle: bool,
+) -> Layer<'a> {
+  let mut rng = utils::create_rng_from_seed(seed_str);
+  let mut original_rng = utils::create_rng_from_seed(seed_str);
+
+  let mut char_to_value_map = utils::make_value_map_for_mode(value_mode, num_bits);
+  let mut original_char_to_value_map = char_to_value_map.clone();
+  utils::shuffle_char_value_map(&mut rng, &mut char_to_value_map);
+  utils::shuffle_char_value_map(&mut original_rng, &mut original_char_to_value_map);
+
+  let values = encode::get_value_vec_from_char_value_mode(
+    payload,
+    num_bits,
+    use_shuffle,
+    &mut rng,
+    &mut char_to_value_map,
+  );
+  // same as encode_char_value_map: one rng reads the values, the
+  // other replays the same shuffles while picking words.
+
+  Layer {
+    seed_str,
+    values,
+    positions,
+    index: 0,
+    rng: original_rng,
+    char_to_value_map: original_char_to_value_map,
+  }
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn encode_deniable(
+  real: &[u8],
+  real_kind: utils::PayloadKind,
+  real_seed: &str,
+  decoy: &[u8],
+  decoy_seed: &str,
+  model: &encode::Model,
+  value_mode: utils::ValueMode,
+  use_shuffle: bool,
+  n_depth: usize,
+  num_bits: usize,
+  length: Option<encode::LengthTarget>,
+  mut chat: Option<chat::Chat>,
+) -> Result<String, String> {
+  match value_mode {
+    utils::ValueMode::CharValueMap(_) | utils::ValueMode::WordAcrostic(_) | utils::ValueMode::WordHash(_) => {},
+    _ => return Err("--decoy only works with the char-value, acrostic and word-hash algorithms".into()),
+  }
+  // those give every word exactly one value per seed. char-bit
+  // and the adaptive algorithms decide per seed which words are skipped
+  // or how many bits they carry, so the two layers would disagree.
+
+  let real = utils::frame_payload(real, real_seed, real_kind);
+  let decoy = utils::frame_payload(decoy, decoy_seed, utils::PayloadKind::Data);
+  let num_bytes = real.len().max(decoy.len());
+  let num_values = (num_bytes * 8).div_ceil(num_bits);
+  let min_words = length.map_or(0, |length| length.words);
+  let num_words = [real_seed, decoy_seed].iter()
+    .map(|seed| encode::get_positions(&value_mode, seed, num_values, min_words).len())
+    .max()
+    .unwrap_or(0);
+  encode::get_num_values_for_length(num_words, length)?;
+  // with acrostic, each seed has its own words that carry a value,
+  // and the text needs enough words for both of them.
+
+  let real = pad_payload(&real, real_seed, num_bytes, num_words);
+  let decoy = pad_payload(&decoy, decoy_seed, num_bytes, num_words);
+  let mut layers = [
+    make_layer(&real, real_seed, encode::get_positions(&value_mode, real_seed, num_values, num_words), &value_mode, num_bits, use_shuffle),
+    make_layer(&decoy, decoy_seed, encode::get_positions(&value_mode, decoy_seed, num_values, num_words), &value_mode, num_bits, use_shuffle),
+  ];
+
+
+  let free_words: Vec<&str> = model.unique_words.iter().filter(|w| !utils::is_punctuation(w)).cloned().collect();
+  let hash_values: Vec<_> = layers.iter()
+    .map(|layer| utils::get_word_hash_values(&free_words, &value_mode, layer.seed_str.as_bytes(), use_shuffle))
+    .collect();
+
+  let mut text_data = String::from("");
+  let initial_words = encode::get_start_words(model, n_depth);
+  let mut current_words = initial_words.clone();
+  for i in 0..num_words {
+    let usable_words: Vec<&str> = free_words.iter()
+      .filter(|w| layers.iter().zip(&hash_values).all(|(layer, layer_hash_values)| {
+        if !layer.positions[i] {
+          return true;
+        }
+        let hash_index = if use_shuffle { Some(layer.index) } else { None };
+        let w_val = match layer_hash_values {
+          Some(values) => values[*w],
+          None => utils::get_word_value(w, &layer.char_to_value_map, &value_mode, layer.seed_str.as_bytes(), hash_index),
+        };
+        w_val == layer.values[layer.index] as usize
+      }))
+      .cloned()
+      .collect();
+    // a word can only be used if it has the right value for both
+    // seeds. that is num_bits * 2 bits of constraints per word,
+    // so it needs a bigger mimic text or fewer bits than usual.
+
+    if usable_words.is_empty() {
+      return Err(format!(
+        "No word in the mimic text has the right value for both seeds at word {}. \
+        Try fewer --bits, or a bigger mimic text",
+        i,
+      ));
+    }
+
+    let (best_word, _) = encode::get_best_word(model, &usable_words, &current_words, n_depth);
+    text_data.push_str(best_word);
+    current_words.push(best_word);
+    chat::push_separator(&mut chat, &mut text_data, &mut current_words, &initial_words);
+
+    for layer in layers.iter_mut().filter(|layer| layer.positions[i]) {
+      if use_shuffle {
+        utils::shuffle_char_value_map(&mut layer.rng, &mut layer.char_to_value_map);
+      }
+      layer.index += 1;
+    }
+  }
+  text_data.pop(); // remove trailing space or newline
+
+  // print summary
+  println!("\nencoded two payloads of {} bytes each (after framing and padding) in {} words", num_bytes, num_words);
+  println!("each word carries {} bits for each seed\n", num_bits);
+
+  Ok(text_data)
+}
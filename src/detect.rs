@@ -0,0 +1,411 @@
+use std::fs;
+
+use clap::ArgMatches;
+use rand::Rng;
+
+use super::analyze;
+use super::corpus;
+use super::encode;
+use super::tokenizer;
+use super::utils;
+
+const NUM_SIGNATURE_LETTERS: usize = 8;
+const FEATURE_NAMES: [&str; 7] = [
+  "perplexity",
+  "letter-frequency",
+  "word-length",
+  "type-token-ratio",
+  "repeated-words",
+  "letter-sets",
+  "letter-set-size",
+];
+const TRAINING_EPOCHS: usize = 2000;
+const LEARNING_RATE: f64 = 0.1;
+const L2_PENALTY: f64 = 0.01;
+
+pub struct CorpusFeatures {
+  pub stats: analyze::TextStats,
+  pub signature_letters: Vec<char>,
+  pub letter_sets: Vec<f64>,
+  pub letter_set_size: f64,
+}
+
+pub struct DetectorModel {
+  pub n: usize,
+  pub bias: f64,
+  pub weights: Vec<f64>,
+  pub means: Vec<f64>,
+  pub scales: Vec<f64>,
+}
+
+pub fn get_signature_letters(corpus_stats: &analyze::TextStats) -> Vec<char> {
+  let mut letters: Vec<usize> = (0..26).collect();
+  letters.sort_by(|a, b| corpus_stats.letter_counts[*b].partial_cmp(&corpus_stats.letter_counts[*a]).unwrap());
+
+  letters.iter().take(NUM_SIGNATURE_LETTERS).map(|i| (b'a' + *i as u8) as char).collect()
+}
+
+pub fn get_letter_set_counts(words: &[&str], signature_letters: &[char]) -> (Vec<f64>, f64) {
+  let mut signature_counts = vec![0.0; 1 << signature_letters.len()];
+  let mut total_set_size = 0.0;
+  let mut num_words = 0.0;
+  for word in words {
+    if utils::is_punctuation(word) {
+      continue;
+    }
+
+    let mut signature = 0;
+    for (i, c) in signature_letters.iter().enumerate() {
+      if word.contains(*c) {
+        signature |= 1 << i;
+      }
+    }
+    signature_counts[signature] += 1.0;
+    total_set_size += (signature as u32).count_ones() as f64;
+    num_words += 1.0;
+  }
+  // the char-bit algorithm picks every word by which of the mapped
+  // letters it contains. those letters are always among the most
+  // common ones, so the set of common letters in each word shows the
+  // bit pattern even without knowing the seed.
+
+  let mean_set_size = if num_words > 0.0 { total_set_size / num_words } else { 0.0 };
+  (signature_counts, mean_set_size)
+}
+
+pub fn get_corpus_features(corpus_words: &[&str]) -> CorpusFeatures {
+  let stats = analyze::get_text_stats(corpus_words);
+  let signature_letters = get_signature_letters(&stats);
+  let (letter_sets, letter_set_size) = get_letter_set_counts(corpus_words, &signature_letters);
+  // the mimic text is the same for every sample, so this is
+  // only counted once.
+
+  CorpusFeatures {
+    stats,
+    signature_letters,
+    letter_sets,
+    letter_set_size,
+  }
+}
+
+pub fn get_features(
+  words: &[&str],
+  corpus_features: &CorpusFeatures,
+  held_out_words: &[&str],
+  ngram_model: &encode::Model,
+  n: usize,
+) -> Vec<f64> {
+  let analysis = analyze::analyze_text(words, &corpus_features.stats, held_out_words, ngram_model, n);
+  let (text_sets, text_set_size) = get_letter_set_counts(words, &corpus_features.signature_letters);
+
+  let perplexity_ratio = if analysis.baseline_perplexity > 0.0 && analysis.perplexity > 0.0 {
+    (analysis.perplexity / analysis.baseline_perplexity).ln()
+  } else {
+    0.0
+  };
+
+  vec![
+    perplexity_ratio,
+    analysis.letter_divergence,
+    analysis.length_divergence,
+    analysis.type_token_ratio - analysis.baseline_type_token_ratio,
+    analysis.repeat_rate - analysis.baseline_repeat_rate,
+    analyze::get_js_divergence(&text_sets, &corpus_features.letter_sets),
+    text_set_size - corpus_features.letter_set_size,
+  ]
+}
+
+pub fn sigmoid(x: f64) -> f64 {
+  1.0 / (1.0 + (-x).exp())
+}
+
+pub fn get_stego_probability(model: &DetectorModel, features: &[f64]) -> f64 {
+  let mut sum = model.bias;
+  for (i, feature) in features.iter().enumerate() {
+    sum += model.weights[i] * (feature - model.means[i]) / model.scales[i];
+  }
+
+  sigmoid(sum)
+}
+
+pub fn train_logistic_regression(samples: &[(Vec<f64>, f64)], n: usize) -> DetectorModel {
+  let num_features = FEATURE_NAMES.len();
+  let num_samples = samples.len() as f64;
+  let mut means = vec![0.0; num_features];
+  let mut scales = vec![0.0; num_features];
+  for (features, _) in samples {
+    for i in 0..num_features {
+      means[i] += features[i] / num_samples;
+    }
+  }
+  for (features, _) in samples {
+    for i in 0..num_features {
+      scales[i] += (features[i] - means[i]).powi(2) / num_samples;
+    }
+  }
+  for scale in scales.iter_mut() {
+    *scale = if *scale > 0.0 { scale.sqrt() } else { 1.0 };
+  }
+  // features are standardized so that one learning rate works for
+  // all of them, no matter what range they are in.
+
+  let mut model = DetectorModel {
+    n,
+    bias: 0.0,
+    weights: vec![0.0; num_features],
+    means,
+    scales,
+  };
+
+  for _ in 0..TRAINING_EPOCHS {
+    let mut bias_gradient = 0.0;
+    let mut weight_gradients = vec![0.0; num_features];
+    for (features, label) in samples {
+      let error = get_stego_probability(&model, features) - label;
+      bias_gradient += error / num_samples;
+      for (i, gradient) in weight_gradients.iter_mut().enumerate() {
+        *gradient += error * (features[i] - model.means[i]) / model.scales[i] / num_samples;
+      }
+    }
+
+    model.bias -= LEARNING_RATE * bias_gradient;
+    for (weight, gradient) in model.weights.iter_mut().zip(weight_gradients.iter()) {
+      *weight -= LEARNING_RATE * (gradient + L2_PENALTY * *weight);
+    }
+  }
+
+  model
+}
+
+pub fn get_accuracy(model: &DetectorModel, samples: &[(Vec<f64>, f64)]) -> f64 {
+  if samples.is_empty() {
+    return 0.0;
+  }
+
+  let num_correct = samples.iter().filter(|(features, label)| {
+    let guess = if get_stego_probability(model, features) >= 0.5 { 1.0 } else { 0.0 };
+    guess == *label
+  }).count();
+
+  num_correct as f64 / samples.len() as f64
+}
+
+pub fn save_model(model: &DetectorModel, file_name: &str) -> Result<(), String> {
+  let mut lines = vec![
+    String::from("text-steg detector"),
+    format!("n {}", model.n),
+    format!("bias {}", model.bias),
+  ];
+  for (i, name) in FEATURE_NAMES.iter().enumerate() {
+    lines.push(format!("feature {} {} {} {}", name, model.weights[i], model.means[i], model.scales[i]));
+  }
+  lines.push(String::new());
+
+  fs::write(file_name, lines.join("\n")).map_err(|e| format!("Failed to write model to '{}': {}", file_name, e))
+}
+
+pub fn parse_model_number<T: std::str::FromStr>(value: Option<&str>, line: &str) -> Result<T, String> {
+  match value.map(str::parse) {
+    Some(Ok(number)) => Ok(number),
+    _ => Err(format!("Invalid line in detector model: '{}'", line)),
+  }
+}
+
+pub fn load_model(file_name: &str) -> Result<DetectorModel, String> {
+  let contents = utils::get_file_contents_as_string(file_name)?;
+  let mut lines = contents.lines();
+  if lines.next() != Some("text-steg detector") {
+    return Err(format!("'{}' is not a detector model", file_name));
+  }
+
+  let mut model = DetectorModel {
+    n: 0,
+    bias: 0.0,
+    weights: vec![],
+    means: vec![],
+    scales: vec![],
+  };
+  for line in lines {
+    let mut parts = line.split_whitespace();
+    match parts.next() {
+      Some("n") => model.n = parse_model_number(parts.next(), line)?,
+      Some("bias") => model.bias = parse_model_number(parts.next(), line)?,
+      Some("feature") => {
+        let name = parts.next();
+        if name != FEATURE_NAMES.get(model.weights.len()).cloned() {
+          return Err(format!("Detector model has unexpected feature: '{}'", line));
+        }
+        model.weights.push(parse_model_number(parts.next(), line)?);
+        model.means.push(parse_model_number(parts.next(), line)?);
+        model.scales.push(parse_model_number(parts.next(), line)?);
+      },
+      None => (),
+      _ => return Err(format!("Invalid line in detector model: '{}'", line)),
+    }
+  }
+
+  if model.n == 0 || model.weights.len() != FEATURE_NAMES.len() {
+    return Err(format!("Detector model '{}' is incomplete", file_name));
+  }
+
+  Ok(model)
+}
+
+pub fn train(matches: &ArgMatches) -> Result<(), String> {
+  let output = utils::get_value(matches, "output")?;
+  let alg_str = utils::get_value(matches, "algorithm")?;
+  let seed_str = utils::get_value(matches, "seed")?;
+  let n_depth = utils::get_numerical_value(matches, "n")?;
+  let num_bits = utils::get_numerical_value(matches, "bits")?;
+  let num_samples = utils::get_numerical_value(matches, "samples")?;
+  let payload_size = utils::get_numerical_value(matches, "payload_size")?;
+  let consecutive_skips = utils::get_numerical_value(matches, "consecutive_skips")?;
+  let depth_skip_threshold = utils::get_numerical_value(matches, "depth_skip")?;
+
+  if !(1..=8).contains(&num_bits) {
+    return Err(format!("Bits must be between 1 and 8 inclusively, you provided {}", num_bits));
+  }
+  if num_samples < 4 {
+    return Err(format!("Need at least 4 samples of each kind to train, you provided {}", num_samples));
+  }
+
+  let (use_shuffle, value_mode) = match utils::get_algorithm_from_string(alg_str, num_bits)? {
+    utils::Algorithm::Shuffle(mode) => (true, mode),
+    utils::Algorithm::NoShuffle(mode) => (false, mode),
+    _ => return Err(format!("Can only train on algorithms that generate text from the mimic file, not '{}'", alg_str)),
+  };
+
+  let mut rng = utils::create_rng_from_seed(seed_str);
+  let policy = tokenizer::get_policy(matches)?;
+  let (word_file_data, held_out) = corpus::hold_out(&corpus::load_corpus(matches)?, analyze::HELD_OUT_FRACTION);
+  let ngram_model = encode::generate_model(&word_file_data, n_depth);
+  let corpus_words: Vec<&str> = word_file_data.text.split_whitespace().collect();
+  let held_out_words: Vec<&str> = held_out.split_whitespace().collect();
+  let (baseline_words, clean_pool) = held_out_words.split_at(held_out_words.len() / 2);
+  // the baseline the features are measured against and the clean
+  // samples come from different halves of the held out text. a clean
+  // sample that overlaps the baseline would look more like it than
+  // a clean text that gets classified later.
+  let corpus_features = get_corpus_features(&corpus_words);
+
+  let mut samples = vec![];
+  for i in 0..num_samples {
+    let payload: Vec<u8> = (0..payload_size).map(|_| rng.gen()).collect();
+    let payload_seed = format!("{:016x}", rng.gen::<u64>());
+    let stego_text = encode::encode_to_string(
+      &payload,
+      &payload_seed,
+      &ngram_model,
+      value_mode,
+      use_shuffle,
+      n_depth,
+      consecutive_skips,
+      depth_skip_threshold,
+      num_bits,
+      None,
+      None,
+    )?;
+    let stego_text = analyze::get_text_words(&stego_text, &policy);
+    let stego_words: Vec<&str> = stego_text.split_whitespace().collect();
+
+    if stego_words.len() >= clean_pool.len() {
+      return Err(format!(
+        "The mimic file is too small: a {} byte payload needs {} words, but half of the held out part of the mimic file only has {}",
+        payload_size,
+        stego_words.len(),
+        clean_pool.len(),
+      ));
+    }
+    let clean_start = rng.gen_range(0, clean_pool.len() - stego_words.len());
+    let clean_words = &clean_pool[clean_start..clean_start + stego_words.len()];
+    // clean samples are pieces of the mimic text with the same number
+    // of words as the stegotext, so the length of a text does not
+    // give away which kind it is. they come from the part the ngram
+    // model was not trained on, like a clean text that gets classified
+    // later, otherwise the detector would learn to tell seen text from
+    // unseen text instead of clean text from stegotext.
+
+    samples.push((get_features(&stego_words, &corpus_features, baseline_words, &ngram_model, n_depth), 1.0));
+    samples.push((get_features(clean_words, &corpus_features, baseline_words, &ngram_model, n_depth), 0.0));
+    println!("generated training sample {} of {}", i + 1, num_samples);
+  }
+
+  let mut training_samples = vec![];
+  let mut held_out_samples = vec![];
+  for (i, sample) in samples.into_iter().enumerate() {
+    if (i / 2) % 4 == 3 {
+      held_out_samples.push(sample);
+    } else {
+      training_samples.push(sample);
+    }
+  }
+  // every 4th pair is held out, so the accuracy on those shows how
+  // well the detector does on text it has not seen.
+
+  let model = train_logistic_regression(&training_samples, n_depth);
+  save_model(&model, output)?;
+
+  // print summary
+  println!("\ntrained on {} samples, held out {} samples", training_samples.len(), held_out_samples.len());
+  println!("training accuracy: {:.1}%", 100.0 * get_accuracy(&model, &training_samples));
+  println!("held out accuracy: {:.1}%", 100.0 * get_accuracy(&model, &held_out_samples));
+  println!("\nfeature weights (positive means more likely stego):");
+  for (name, weight) in FEATURE_NAMES.iter().zip(model.weights.iter()) {
+    println!("  {:<18}{:>8.3}", name, weight);
+  }
+  println!("\nsaved detector model to {}\n", output);
+
+  Ok(())
+}
+
+pub fn classify(matches: &ArgMatches) -> Result<(), String> {
+  let file = utils::get_value(matches, "file")?;
+  let model_file_name = utils::get_value(matches, "model")?;
+
+  let model = load_model(model_file_name)?;
+  let policy = tokenizer::get_policy(matches)?;
+  let text = analyze::get_text_words(&utils::get_file_contents_as_string(file)?, &policy);
+  let (word_file_data, held_out) = corpus::hold_out(&corpus::load_corpus(matches)?, analyze::HELD_OUT_FRACTION);
+  let ngram_model = encode::generate_model(&word_file_data, model.n);
+
+  let words: Vec<&str> = text.split_whitespace().collect();
+  let corpus_words: Vec<&str> = word_file_data.text.split_whitespace().collect();
+  let held_out_words: Vec<&str> = held_out.split_whitespace().collect();
+  if words.is_empty() {
+    return Err(format!("'{}' does not contain any words", file));
+  }
+
+  let corpus_features = get_corpus_features(&corpus_words);
+  let features = get_features(
+    &words,
+    &corpus_features,
+    &held_out_words,
+    &ngram_model,
+    model.n,
+  );
+  let probability = get_stego_probability(&model, &features);
+
+  println!("\nfeature contributions:");
+  for (i, name) in FEATURE_NAMES.iter().enumerate() {
+    let contribution = model.weights[i] * (features[i] - model.means[i]) / model.scales[i];
+    println!("  {:<18}{:>8.3}", name, contribution);
+  }
+  println!("\nprobability of stegotext: {:.1}%", 100.0 * probability);
+  if probability >= 0.5 {
+    println!("{} is likely stegotext\n", file);
+  } else {
+    println!("{} is likely clean text\n", file);
+  }
+
+  Ok(())
+}
+
+pub fn detect(matches: &ArgMatches) -> Result<(), String> {
+  if let Some(train_matches) = matches.subcommand_matches("train") {
+    train(train_matches)
+  } else if let Some(classify_matches) = matches.subcommand_matches("classify") {
+    classify(classify_matches)
+  } else {
+    Err(String::from("Must provide detect command: either 'train' or 'classify'"))
+  }
+}
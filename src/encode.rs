@@ -6,11 +6,49 @@ use clap::ArgMatches;
 use rand::{Rng, prelude::StdRng};
 use bitstream_io::{BigEndian, BitReader};
 use ngrams::Ngram;
-use rpassword;
+use zeroize::Zeroizing;
 
 use super::utils;
+use super::archive;
+use super::deniable;
+use super::format;
+use super::chat;
+use super::corpus;
+use super::synonym;
+use super::invisible;
+use super::password;
+use super::pos;
+use super::pubkey;
+use super::secret;
+use super::split;
+use super::template;
+use super::tokenizer;
+use super::topic;
+use super::vocabulary;
+
+pub struct MixtureComponent<'a> {
+  pub gram: HashMap<Vec<&'a str>, usize>,
+  pub total_words: f64,
+  pub weight: f64,
+}
+
+pub struct Model<'a> {
+  pub gram: HashMap<Vec<&'a str>, usize>,
+  pub unique_words: Vec<&'a str>,
+  pub total_words: f64,
+  pub components: Vec<MixtureComponent<'a>>,
+  pub tags: Option<pos::TagModel<'a>>,
+  pub topic: Option<topic::TopicModel<'a>>,
+  pub context: Vec<String>,
+}
+
+#[derive(Clone, Copy)]
+pub struct LengthTarget {
+  pub words: usize,
+  pub exact: bool,
+}
 
-pub fn generate_ngrams<'a>(text: &'a String, n: usize) -> (HashMap<Vec<&str>, usize>, Vec<&str>, usize) {
+pub fn generate_ngrams(text: &str, n: usize) -> (HashMap<Vec<&str>, usize>, Vec<&str>, usize) {
   let mut n_down = n;
   let mut hash: HashMap<Vec<&str>, usize> = HashMap::new();
   let mut unique_words: Vec<&str> = vec![];
@@ -65,7 +103,72 @@ pub fn generate_ngrams<'a>(text: &'a String, n: usize) -> (HashMap<Vec<&str>, us
   (hash, unique_words, total_words)
 }
 
-pub fn get_restricted_chars(char_map: &HashMap<char, usize>, gib_word: &String) -> Vec<char> {
+pub fn generate_corpus_ngrams(word_file_data: &corpus::Corpus, n: usize) -> (HashMap<Vec<&str>, usize>, Vec<&str>, usize) {
+  if word_file_data.parts.len() <= 1 {
+    return generate_ngrams(&word_file_data.text, n);
+  }
+
+  let mut gram: HashMap<Vec<&str>, usize> = HashMap::new();
+  let mut unique_words = vec![];
+  let mut total_words = 0;
+  for part in &word_file_data.parts {
+    let (part_gram, part_unique_words, part_total_words) = generate_ngrams(&word_file_data.text[part.start..part.end], n);
+    for w in part_unique_words {
+      if !gram.contains_key(&vec![w]) {
+        unique_words.push(w);
+      }
+    }
+    for (key, count) in part_gram {
+      *gram.entry(key).or_insert(0) += count;
+    }
+    total_words += part_total_words;
+  }
+  // the parts are joined with a space in the corpus text, but the
+  // last words of one mimic text and the first words of the next
+  // were never written together, so no ngram may span two parts.
+
+  (gram, unique_words, total_words)
+}
+
+pub fn generate_model<'a>(word_file_data: &'a corpus::Corpus, n: usize) -> Model<'a> {
+  let (gram, unique_words, total_words) = generate_corpus_ngrams(word_file_data, n);
+
+  let mut components = vec![];
+  if word_file_data.parts.len() > 1 {
+    for part in &word_file_data.parts {
+      let (part_gram, _, part_total_words) = generate_ngrams(&word_file_data.text[part.start..part.end], n);
+      components.push(MixtureComponent {
+        gram: part_gram,
+        total_words: part_total_words as f64,
+        weight: part.weight,
+      });
+    }
+  }
+  // the ngrams of all the mimic texts together decide which words
+  // and contexts exist, the parts only decide how likely a word is.
+
+  Model {
+    gram,
+    unique_words,
+    total_words: total_words as f64,
+    components,
+    tags: None,
+    topic: None,
+    context: vec![],
+  }
+}
+
+pub fn build_model<'a>(matches: &ArgMatches, word_file_data: &'a corpus::Corpus, n: usize) -> Result<Model<'a>, String> {
+  let vocabulary = vocabulary::get_vocabulary(matches)?;
+  let mut model = generate_model(word_file_data, n);
+  model.unique_words = vocabulary::filter_words(&model.unique_words, &model.gram, &vocabulary);
+  model.tags = pos::get_tag_model(matches, &word_file_data.text)?;
+  model.topic = topic::get_topic_model(matches, &word_file_data.text, &model.gram)?;
+
+  Ok(model)
+}
+
+pub fn get_restricted_chars(char_map: &HashMap<char, usize>, gib_word: &str) -> Vec<char> {
   let mut restricted_chars = vec![]; 
   for key in char_map.keys() {
     restricted_chars.push(*key);
@@ -81,7 +184,7 @@ pub fn get_restricted_chars(char_map: &HashMap<char, usize>, gib_word: &String)
   restricted_chars
 }
 
-pub fn can_use_word(word: &str, good_chars: &Vec<char>, restricted_chars: &Vec<char>) -> bool {
+pub fn can_use_word(word: &str, good_chars: &[char], restricted_chars: &[char]) -> bool {
   let word_chars: Vec<char> = word.chars().collect();
   let mut good_chars_used = vec![0; good_chars.len()];
   for c in word_chars {
@@ -103,7 +206,7 @@ pub fn can_use_word(word: &str, good_chars: &Vec<char>, restricted_chars: &Vec<c
   true
 }
 
-pub fn get_initial_words<'a>(hashmap: &'a HashMap<Vec<&str>, usize>, n: usize) -> Vec<&'a str> {
+pub fn count_initial_words<'a>(hashmap: &'a HashMap<Vec<&str>, usize>, n: usize) -> HashMap<Vec<&'a str>, usize> {
   let mut vecs_with_n_items = vec![];
   for key in hashmap.keys() {
     if key.len() == n {
@@ -115,15 +218,13 @@ pub fn get_initial_words<'a>(hashmap: &'a HashMap<Vec<&str>, usize>, n: usize) -
 
   for vec in vecs_with_n_items {
     let mut n_minus_1_slice = vec![];
-    let mut counter = 1;
 
-    for word in vec {
+    for (counter, word) in (1..).zip(vec) {
       if counter < n {
         n_minus_1_slice.push(*word);
       } else {
         break;
       }
-      counter += 1;
     }
 
     if let Some(val) = count_hash.get_mut(&n_minus_1_slice) {
@@ -133,6 +234,12 @@ pub fn get_initial_words<'a>(hashmap: &'a HashMap<Vec<&str>, usize>, n: usize) -
     }
   }
 
+  count_hash
+}
+
+pub fn get_initial_words<'a>(hashmap: &'a HashMap<Vec<&str>, usize>, n: usize) -> Vec<&'a str> {
+  let count_hash = count_initial_words(hashmap, n);
+
   let mut best_vec_count = 0;
   let mut best_vec = vec![];
   for vec in count_hash.keys() {
@@ -146,7 +253,36 @@ pub fn get_initial_words<'a>(hashmap: &'a HashMap<Vec<&str>, usize>, n: usize) -
   best_vec
 }
 
-pub fn get_probability_of(word: &str, given: &Vec<&str>, hashmap: &HashMap<Vec<&str>, usize>, num_words: f64) -> f64 {
+pub fn get_sorted_initial_words<'a>(hashmap: &'a HashMap<Vec<&str>, usize>, n: usize) -> Vec<&'a str> {
+  let count_hash = count_initial_words(hashmap, n);
+
+  let mut best_vec_count = 0;
+  let mut best_vec = vec![];
+  for vec in count_hash.keys() {
+    let vec_count = count_hash.get(vec).unwrap();
+    if vec_count > &best_vec_count || (vec_count == &best_vec_count && vec < &best_vec) {
+      best_vec_count = *vec_count;
+      best_vec = vec.to_vec();
+    }
+    // ties are broken alphabetically so that the initial words do
+    // not depend on the hash map iteration order. the adaptive decoder
+    // needs to derive the exact same starting context as the encoder,
+    // the other algorithms do not, so they keep picking like before.
+  }
+
+  best_vec
+}
+
+pub fn get_start_words<'a>(model: &'a Model, n: usize) -> Vec<&'a str> {
+  if model.context.is_empty() {
+    return get_initial_words(&model.gram, n);
+  }
+  model.context.iter().map(String::as_str).collect()
+  // with a template, the text goes on from the template words
+  // before it, instead of from the most common start of the mimic text.
+}
+
+pub fn get_relative_frequency(word: &str, given: &[&str], hashmap: &HashMap<Vec<&str>, usize>, num_words: f64) -> f64 {
   let count_of_given = match given.len() {
     0 => num_words,
     _ => {
@@ -158,7 +294,7 @@ pub fn get_probability_of(word: &str, given: &Vec<&str>, hashmap: &HashMap<Vec<&
     },
   };
 
-  let mut word_vec = given.clone();
+  let mut word_vec = given.to_vec();
   word_vec.push(word);
   let count_of_sequence = if let Some(count) = hashmap.get(&word_vec) {
     *count as f64
@@ -169,13 +305,69 @@ pub fn get_probability_of(word: &str, given: &Vec<&str>, hashmap: &HashMap<Vec<&
   count_of_sequence / count_of_given
 }
 
+pub fn get_probability_of(word: &str, given: &[&str], model: &Model) -> f64 {
+  if model.components.is_empty() {
+    return get_relative_frequency(word, given, &model.gram, model.total_words);
+  }
+
+  model.components.iter()
+    .map(|component| component.weight * get_relative_frequency(word, given, &component.gram, component.total_words))
+    .sum()
+  // linear interpolation: P(w | given) = sum of weight_i * P_i(w | given).
+  // a context that only one of the mimic texts has still gives a
+  // probability, so mixing texts means fewer fallbacks to random words.
+}
+
+pub fn get_bias(word: &str, current_words: &[&str], model: &Model) -> f64 {
+  let mut bias = 1.0;
+  if let Some(tag_model) = &model.tags {
+    bias *= pos::get_tag_probability(tag_model, word, current_words);
+  }
+  if let Some(topic_model) = &model.topic {
+    bias *= topic::get_topic_bias(topic_model, word);
+  }
+  // with --pos, words whose part of speech fits the sentence so far,
+  // and with --topic, words related to the topic, win over words the
+  // ngrams alone would have picked.
+
+  bias
+}
+
+pub fn get_word_score(word: &str, given: &[&str], current_words: &[&str], model: &Model) -> f64 {
+  let p = get_probability_of(word, given, model);
+  if p > 0.0 {
+    p * get_bias(word, current_words, model)
+  } else {
+    p
+  }
+}
+
+pub fn sample_by_bias(usable_words: &[&str], current_words: &[&str], model: &Model, rng: &mut impl Rng) -> usize {
+  if model.tags.is_none() && model.topic.is_none() {
+    return rng.gen_range(0, usable_words.len());
+  }
+
+  let biases: Vec<f64> = usable_words.iter().map(|w| get_bias(w, current_words, model)).collect();
+  let total_bias: f64 = biases.iter().sum();
+  let mut target = rng.gen::<f64>() * total_bias;
+  for (i, bias) in biases.iter().enumerate() {
+    if target < *bias {
+      return i;
+    }
+    target -= bias;
+  }
+  usable_words.len() - 1
+  // when the ngrams know nothing about any of the candidates, a word
+  // that fits the expected tag or the topic is still better than any
+  // random word.
+}
+
 
 pub fn get_best_word<'a>(
-  gram: &HashMap<Vec<&str>, usize>,
-  usable_words: &Vec<&'a str>,
-  current_words: &Vec<&str>,
+  model: &Model,
+  usable_words: &[&'a str],
+  current_words: &[&str],
   n: usize,
-  total_words: f64,
 ) -> (&'a str, usize) {
   let mut all_p_zero = true;
   let mut use_n = n;
@@ -183,12 +375,8 @@ pub fn get_best_word<'a>(
 
   while all_p_zero {
     let mut ngram_slice = vec![];
-    let mut counter = 1;
-    for word in current_words.iter().rev() {
-      if counter < use_n {
-        ngram_slice.push(*word);
-      }
-      counter += 1;
+    for word in current_words.iter().rev().take(use_n - 1) {
+      ngram_slice.push(*word);
     }
     ngram_slice.reverse();
 
@@ -196,12 +384,11 @@ pub fn get_best_word<'a>(
     let mut max_p = 0.0;
     max_p_index = 0;
 
-    for i in 0..usable_words.len() {
-      let w = &usable_words[i];
+    for (i, w) in usable_words.iter().enumerate() {
       if w == last_word {
         continue;
       }
-      let p = get_probability_of(w, &ngram_slice, gram, total_words);
+      let p = get_word_score(w, &ngram_slice, current_words, model);
       // let p = get_interpolated_probability(w, &ngram_slice, gram, total_words);
       // println!("P({} | {:?}) = {}", w, ngram_slice, p);
       if p > max_p {
@@ -229,30 +416,268 @@ pub fn get_best_word<'a>(
 
   if use_n == 0 {
     let mut rng = rand::thread_rng();
-    max_p_index = rng.gen_range(0, usable_words.len());
+    max_p_index = sample_by_bias(usable_words, current_words, model, &mut rng);
   }
 
   (usable_words[max_p_index], use_n)
 }
 
 
+pub fn sample_next_word<'a>(
+  model: &Model,
+  usable_words: &[&'a str],
+  current_words: &[&str],
+  n: usize,
+) -> &'a str {
+  let mut rng = rand::thread_rng();
+  let mut use_n = n;
+
+  loop {
+    let mut ngram_slice = vec![];
+    for word in current_words.iter().rev().take(use_n - 1) {
+      ngram_slice.push(*word);
+    }
+    ngram_slice.reverse();
+
+    let last_word = current_words.last();
+    let mut probabilities = vec![];
+    let mut total_p = 0.0;
+    for w in usable_words {
+      let p = if Some(w) == last_word {
+        0.0
+      } else {
+        get_word_score(w, &ngram_slice, current_words, model)
+      };
+      total_p += p;
+      probabilities.push(p);
+    }
+
+    if total_p > 0.0 {
+      let mut target = rng.gen::<f64>() * total_p;
+      for (i, p) in probabilities.iter().enumerate() {
+        if target < *p {
+          return usable_words[i];
+        }
+        target -= p;
+      }
+      return usable_words[usable_words.len() - 1];
+    }
+
+    if use_n == 1 {
+      return usable_words[sample_by_bias(usable_words, current_words, model, &mut rng)];
+    }
+    use_n -= 1;
+  }
+  // unlike get_best_word, this does not always pick the most likely word.
+  // it is used for words that do not carry any data, and always picking
+  // the most likely word would keep repeating the same phrases.
+}
+
+pub fn generate_followers<'a>(gram: &HashMap<Vec<&'a str>, usize>) -> HashMap<Vec<&'a str>, Vec<&'a str>> {
+  let mut followers: HashMap<Vec<&str>, Vec<&str>> = HashMap::new();
+  for key in gram.keys() {
+    if key.len() < 2 {
+      continue;
+    }
+    followers.entry(key[..key.len() - 1].to_vec()).or_default().push(key[key.len() - 1]);
+  }
+  for words in followers.values_mut() {
+    words.sort_unstable();
+  }
+
+  followers
+  // maps every context to the words that were seen right after it,
+  // so a word can be sampled from the few words that can follow
+  // instead of scoring every word of the mimic text.
+}
+
+pub fn sample_sentence_word<'a>(
+  model: &Model,
+  followers: &HashMap<Vec<&'a str>, Vec<&'a str>>,
+  candidates: &[&'a str],
+  current_words: &[&str],
+  n: usize,
+  allow_end: bool,
+) -> &'a str {
+  let last_word = current_words.last();
+  for use_n in (2..=n).rev() {
+    if current_words.len() < use_n - 1 {
+      continue;
+    }
+
+    let context = &current_words[current_words.len() - (use_n - 1)..];
+    if let Some(words) = followers.get(context) {
+      let usable_words: Vec<&str> = words.iter()
+        .filter(|w| (allow_end || !utils::is_sentence_end(w)) && Some(*w) != last_word)
+        .copied()
+        .collect();
+      if !usable_words.is_empty() {
+        return sample_next_word(model, &usable_words, current_words, use_n);
+      }
+    }
+  }
+
+  sample_next_word(model, candidates, current_words, 1)
+  // same backoff as sample_next_word, but every word that is not a
+  // follower of the context has a probability of 0 anyway, so only the
+  // followers get scored. only when no context has one, every candidate is.
+}
+
+pub fn generate_continuation_counts<'a>(gram: &HashMap<Vec<&'a str>, usize>) -> HashMap<Vec<&'a str>, usize> {
+  let mut continuations = HashMap::new();
+  for key in gram.keys() {
+    let last_word = key[key.len() - 1];
+    if utils::is_punctuation(last_word) {
+      continue;
+    }
+
+    let context = key[..key.len() - 1].to_vec();
+    if let Some(val) = continuations.get_mut(&context) {
+      *val += 1;
+    } else {
+      continuations.insert(context, 1);
+    }
+    // every key in the ngram hash is unique, so counting the keys
+    // that start with a given context gives the number of distinct
+    // words that have been seen following that context. unigrams have an
+    // empty context, so the empty vec counts every unique word.
+  }
+
+  continuations
+}
+
+pub fn get_adaptive_context_len(
+  continuations: &HashMap<Vec<&str>, usize>,
+  current_words: &[&str],
+  n: usize,
+) -> Option<(usize, usize)> {
+  let mut use_n = n;
+  while use_n > 0 {
+    let context_len = use_n - 1;
+    if current_words.len() >= context_len {
+      let context = &current_words[current_words.len() - context_len..];
+      if let Some(num_candidates) = continuations.get(context) {
+        if *num_candidates > 1 {
+          return Some((context_len, *num_candidates));
+        }
+      }
+    }
+    use_n -= 1;
+    // same stupid backoff as get_best_word: if the deepest context
+    // only has one (or no) candidate, look at a shorter context instead.
+  }
+
+  None
+}
+
+pub fn get_adaptive_bits(
+  continuations: &HashMap<Vec<&str>, usize>,
+  current_words: &[&str],
+  n: usize,
+  max_bits: usize,
+) -> usize {
+  match get_adaptive_context_len(continuations, current_words, n) {
+    Some((_, num_candidates)) => utils::get_bits_for_candidates(num_candidates, max_bits),
+    None => 1,
+  }
+}
+
+
+pub fn get_length_target(matches: &ArgMatches) -> Result<Option<LengthTarget>, String> {
+  let (name, exact) = if matches.is_present("target_words") {
+    ("target_words", true)
+  } else if matches.is_present("min_words") {
+    ("min_words", false)
+  } else {
+    return Ok(None);
+  };
+  let words = utils::get_numerical_value(matches, name)?;
+  if words == 0 {
+    return Err("The number of words must be at least 1".into());
+  }
+
+  Ok(Some(LengthTarget {
+    words,
+    exact,
+  }))
+}
+
+pub fn add_filler(contents: &[u8], seed_str: &str, num_bytes: usize) -> Vec<u8> {
+  let filler_seed = Zeroizing::new(format!("{}:filler", seed_str));
+  let mut rng = utils::create_rng_from_seed(&filler_seed);
+  let mut padded = contents.to_vec();
+  padded.extend((0..num_bytes).map(|_| rng.gen::<u8>()));
+  // the filler looks like any other data, but it comes after the
+  // end that the payload header gives, so the decoder throws it away.
+
+  padded
+}
+
+pub fn get_positions(value_mode: &utils::ValueMode, seed_str: &str, num_values: usize, min_words: usize) -> Vec<bool> {
+  if let utils::ValueMode::WordAcrostic(_) = value_mode {
+    let mut rng = utils::create_position_rng(seed_str);
+    let mut positions = vec![];
+    let mut num_data = 0;
+    while num_data < num_values || positions.len() < min_words {
+      let is_data = utils::is_data_position(&mut rng);
+      if is_data {
+        num_data += 1;
+      }
+      positions.push(is_data);
+    }
+    return positions;
+  }
+  // the same seed always gives the same positions, so more
+  // positions can be asked for without changing the first ones.
+
+  vec![true; num_values.max(min_words)]
+}
+
+pub fn get_positions_for_length(
+  value_mode: &utils::ValueMode,
+  seed_str: &str,
+  num_values: usize,
+  length: Option<LengthTarget>,
+) -> Result<Vec<bool>, String> {
+  let positions = get_positions(value_mode, seed_str, num_values, length.map_or(0, |length| length.words));
+  get_num_values_for_length(positions.len(), length)?;
+
+  Ok(positions)
+}
+
+pub fn get_num_values_for_length(num_values: usize, length: Option<LengthTarget>) -> Result<usize, String> {
+  match length {
+    Some(length) if length.exact && num_values > length.words => Err(format!(
+      "The payload needs {} words, more than the {} of --target-words. \
+      Use a bigger --target-words, more --bits or a smaller payload",
+      num_values,
+      length.words,
+    )),
+    Some(length) => Ok(num_values.max(length.words)),
+    None => Ok(num_values),
+  }
+}
+
+
+#[allow(clippy::too_many_arguments)]
 pub fn wordify(
-  gram: &HashMap<Vec<&str>, usize>,
+  model: &Model,
   n: usize,
   file_words: Vec<String>,
   rng: &mut StdRng,
   bit_to_char_map: &mut HashMap<usize, char>,
-  unique_words: &Vec<&str>,
-  total_words: f64,
   consecutive_skips: usize,
   depth_skip_threshold: usize,
   use_shuffle: bool,
+  length: Option<LengthTarget>,
+  chat: &mut Option<chat::Chat>,
 ) -> Result<String, String> {
+  get_num_values_for_length(file_words.len(), length)?;
   let mut char_to_bit_map = HashMap::new();
   let mut num_bits = 0;
   for bit_val in bit_to_char_map.keys() {
     num_bits += 1;
-    char_to_bit_map.insert(*bit_to_char_map.get(&bit_val).unwrap(), *bit_val);
+    char_to_bit_map.insert(*bit_to_char_map.get(bit_val).unwrap(), *bit_val);
   }
   num_bits -= 1;
 
@@ -262,13 +687,14 @@ pub fn wordify(
   let mut skip_count = 0;
   let mut n_gram_used = vec![0; n];
   let mut text_data = String::from("");
-  let mut current_words = get_initial_words(gram, n);
+  let initial_words = get_start_words(model, n);
+  let mut current_words = initial_words.clone();
   let mut i = 0;
   let mut consecutive_skips_used = 0;
 
   let mut skip_words = vec![];
   if !use_shuffle {
-    for w in unique_words {
+    for w in &model.unique_words {
       if utils::is_skip_word(w, &char_to_bit_map) {
         skip_words.push(*w);
       }
@@ -280,6 +706,10 @@ pub fn wordify(
   while i < file_words.len() {
     let gibberish_word = &file_words[i];
     let mut used_skip_word = false;
+    let num_words = succ_count + skip_count + fail_count + file_words.len() - i;
+    let extra_words = length.map_or(0, |l| l.words.saturating_sub(num_words));
+    // the number of skip words still needed to reach the target,
+    // if every word left in the file gets encoded without a skip.
 
     let mut use_keys = vec![]; 
     for key in char_to_bit_map.keys() {
@@ -297,7 +727,7 @@ pub fn wordify(
     }
 
     // let mut value_num_list = vec![0; max_value];
-    for w in unique_words {
+    for w in &model.unique_words {
       // let word_val = get_value_from_word(w, char_value_map, max_value);
       // // println!("value for {}: {}", w, get_value_from_word(w, char_value_map, 2));
       // value_num_list[word_val] += 1;
@@ -306,17 +736,28 @@ pub fn wordify(
         skip_words.push(*w);
       }
 
-      if can_use_word(w, &gibberish_word.chars().collect(), &restricted_chars) {
+      if can_use_word(w, &gibberish_word.chars().collect::<Vec<char>>(), &restricted_chars) {
         usable_words.push(*w);
       }
     }
 
+    if extra_words > 0 && !skip_words.is_empty() && rand::thread_rng().gen_range(0, extra_words + file_words.len() - i) < extra_words {
+      let skip_word = sample_next_word(model, &skip_words, &current_words, n);
+      current_words.push(skip_word);
+      text_data.push_str(skip_word);
+      chat::push_separator(chat, &mut text_data, &mut current_words, &initial_words);
+      skip_count += 1;
+      continue;
+    }
+    // spread the extra skip words over the whole text instead
+    // of putting them all at the end.
+
     match usable_words.len() {
       0 => {
         fail_count += 1;
-        text_data.push_str(&gibberish_word);
-        text_data.push_str(" ");
+        text_data.push_str(gibberish_word);
         current_words.push(".");
+        chat::push_separator(chat, &mut text_data, &mut current_words, &initial_words);
         consecutive_skips_used = 0;
         // if there are NO usable words at all then we 'failed'
         // to encode this word. we push the gibberish word as is to
@@ -329,7 +770,7 @@ pub fn wordify(
         let best_word = &usable_words[0];
         text_data.push_str(best_word);
         current_words.push(best_word);
-        text_data.push_str(" ");
+        chat::push_separator(chat, &mut text_data, &mut current_words, &initial_words);
         n_gram_used[0] += 1;
         consecutive_skips_used = 0;
         // there is only one usable word, so use it without
@@ -338,11 +779,10 @@ pub fn wordify(
       },
       _ => {
         let (best_word, n_used) = get_best_word(
-          gram,
+          model,
           &usable_words,
           &current_words,
           n,
-          total_words,
         );
 
         // user can fine-tune the quality of the text output using depth_skip_threshold
@@ -354,23 +794,22 @@ pub fn wordify(
         // loop forever in certain situations.
         // depth skip threshold allows user to say which n-depths are acceptable.
         // lower n-depths produce less realistic.
-        if n_used <= depth_skip_threshold && consecutive_skips_used < consecutive_skips && skip_words.len() > 0 {
+        let room_for_skip = length.map_or(true, |l| !l.exact || num_words < l.words);
+        if n_used <= depth_skip_threshold && consecutive_skips_used < consecutive_skips && !skip_words.is_empty() && room_for_skip {
           let (best_word2, n_used2) = get_best_word(
-            gram,
+            model,
             &skip_words,
             &current_words,
             n,
-            total_words
           );
 
           n_gram_used[n_used2] += 1;
           current_words.push(best_word2);
           text_data.push_str(best_word2);
-          text_data.push_str(" ");
+          chat::push_separator(chat, &mut text_data, &mut current_words, &initial_words);
           skip_count += 1;
           used_skip_word = true;
           consecutive_skips_used += 1;
-          i -= 1;
           // we used a skip word, make sure to keep i at its current
           // level so that we try to encode this word again
         } else {
@@ -378,7 +817,7 @@ pub fn wordify(
           n_gram_used[n_used] += 1;
           text_data.push_str(best_word);
           current_words.push(best_word);
-          text_data.push_str(" ");
+          chat::push_separator(chat, &mut text_data, &mut current_words, &initial_words);
           consecutive_skips_used = 0;
           // if not using a skip word, we encoded the best possible word according
           // to ngrams. add the best word to the text output, as well as the current
@@ -396,10 +835,30 @@ pub fn wordify(
       char_to_bit_map = utils::make_char_to_bit_map(bit_to_char_map);
     }
 
-    i += 1;
+    if !used_skip_word {
+      i += 1;
+    }
   }
 
-  text_data.pop(); // remove trailing space
+  let num_words = succ_count + skip_count + fail_count;
+  let extra_words = length.map_or(0, |l| l.words.saturating_sub(num_words));
+  if extra_words > 0 && use_shuffle {
+    skip_words = model.unique_words.iter().filter(|w| utils::is_skip_word(w, &char_to_bit_map)).cloned().collect();
+  }
+  if extra_words > 0 && skip_words.is_empty() {
+    return Err("There are no skip words in the mimic text to reach the number of words".into());
+  }
+  for _ in 0..extra_words {
+    let skip_word = sample_next_word(model, &skip_words, &current_words, n);
+    current_words.push(skip_word);
+    text_data.push_str(skip_word);
+    chat::push_separator(chat, &mut text_data, &mut current_words, &initial_words);
+    skip_count += 1;
+  }
+  // whatever is still missing goes at the end. skip words decode to
+  // nothing, so the decoder does not see them.
+
+  text_data.pop(); // remove trailing space or newline
 
   let num_bytes = (file_words.len() * num_bits) / 8;
   // print summary
@@ -415,7 +874,7 @@ pub fn wordify(
 }
 
 pub fn get_value_vec_from_char_value_mode(
-  file_contents: &Vec<u8>,
+  file_contents: &[u8],
   num_bits: usize,
   use_shuffle: bool,
   rng: &mut StdRng,
@@ -427,7 +886,7 @@ pub fn get_value_vec_from_char_value_mode(
   let mut value_vec = vec![];
 
   while num_bits_remain > 0 {
-    let num_bits_to_read = if num_bits_remain < num_bits as usize {
+    let num_bits_to_read = if num_bits_remain < num_bits {
       num_bits_remain as u32
     } else {
       num_bits as u32
@@ -447,7 +906,7 @@ pub fn get_value_vec_from_char_value_mode(
 
 pub fn get_value_vec(
   bit_to_char_map: &mut HashMap<usize, char>,
-  file_contents: &Vec<u8>,
+  file_contents: &[u8],
   num_bits: usize,
   use_shuffle: bool,
   rng: &mut StdRng,
@@ -472,7 +931,7 @@ pub fn get_value_vec(
   // of bits is 3, then the sorted keys will look like: [4, 2, 1, 0]
 
   while num_bits_remain > 0 {
-    let num_bits_to_read = if num_bits_remain < num_bits as usize {
+    let num_bits_to_read = if num_bits_remain < num_bits {
       num_bits_remain as u32
     } else {
       num_bits as u32
@@ -496,74 +955,229 @@ pub fn get_value_vec(
   value_vec
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn wordify_from_char_value_mode(
-  gram: &HashMap<Vec<&str>, usize>,
+  model: &Model,
   char_to_value_map: &mut HashMap<char, usize>,
   n: usize,
   file_values: Vec<u8>,
+  positions: &[bool],
   num_bits: usize,
-  unique_words: &Vec<&str>,
-  total_words: f64,
   use_shuffle: bool,
   value_mode: utils::ValueMode,
   rng: &mut StdRng,
+  hash_key: &[u8],
+  chat: &mut Option<chat::Chat>,
 ) -> Result<String, String> {
   let mut succ_count = 0;
   let mut n_gram_used = vec![0; n];
   let mut text_data = String::from("");
-  let mut current_words = get_initial_words(gram, n);
+  let initial_words = get_start_words(model, n);
+  let mut current_words = initial_words.clone();
   let mut i = 0;
 
+  let free_words: Vec<&str> = model.unique_words.iter().filter(|w| !utils::is_punctuation(w)).cloned().collect();
+  // words that do not carry a value can be any word
+  let hash_values = utils::get_word_hash_values(&free_words, &value_mode, hash_key, use_shuffle);
 
-  while i < file_values.len() {
-    let current_val = file_values[i];
-
+  for is_data in positions {
     let mut usable_words = vec![];
 
-    for w in unique_words {
-      if *w == "." || *w == "," || *w == "?" || *w == ";" || *w == "!" {
-        // dont use punctuation in char_value mode because
-        // punctuation isnt ignored by the decoder. if you want
-        // to leave punctuation in, you would also have to leave
-        // the spaces around them which would result in a stego text
-        // like: he likes cars , toys , and trucks .
-        // for that reason, I chose to ignore punctuation
-        continue;
+    if !*is_data {
+      usable_words = free_words.clone();
+    } else {
+      let current_val = file_values[i];
+      for w in &model.unique_words {
+        if utils::is_punctuation(w) {
+          // dont use punctuation in char_value mode because
+          // punctuation isnt ignored by the decoder. if you want
+          // to leave punctuation in, you would also have to leave
+          // the spaces around them which would result in a stego text
+          // like: he likes cars , toys , and trucks .
+          // for that reason, I chose to ignore punctuation
+          continue;
+        }
+
+        let hash_index = if use_shuffle { Some(i) } else { None };
+        let w_val = match &hash_values {
+          Some(values) => values[w],
+          None => utils::get_word_value(w, char_to_value_map, &value_mode, hash_key, hash_index),
+        };
+        if w_val == current_val as usize {
+          usable_words.push(*w);
+        }
       }
 
-      let w_val = utils::get_value_from_chars(w, &char_to_value_map, &value_mode);
-      if w_val == current_val as usize {
-        usable_words.push(*w);
+      if usable_words.is_empty() {
+        return Err(format!("Not enough words with value {} using {} bits", current_val, num_bits));
       }
     }
 
 
     match usable_words.len() {
-      0 => {
-        panic!("NOT ENOUGH WORDS WITH VALUE {}", current_val);
-      },
       1 => {
         succ_count += 1;
         let best_word = &usable_words[0];
         text_data.push_str(best_word);
         current_words.push(best_word);
-        text_data.push_str(" ");
+        chat::push_separator(chat, &mut text_data, &mut current_words, &initial_words);
         n_gram_used[0] += 1;
       },
       _ => {
         let (best_word, n_used) = get_best_word(
-          gram,
+          model,
           &usable_words,
           &current_words,
           n,
-          total_words,
         );
 
         succ_count += 1;
         n_gram_used[n_used] += 1;
         text_data.push_str(best_word);
         current_words.push(best_word);
-        text_data.push_str(" ");
+        chat::push_separator(chat, &mut text_data, &mut current_words, &initial_words);
+      }
+    };
+
+    if *is_data {
+      if use_shuffle {
+        utils::shuffle_char_value_map(rng, char_to_value_map);
+      }
+
+      i += 1;
+    }
+  }
+
+  text_data.pop(); // remove trailing space or newline
+
+  let num_bytes = (file_values.len() * num_bits) / 8;
+  // print summary
+  println!("\nencoding using {} bits per word. file had {} bytes, ie: {} words to wordify", num_bits, num_bytes, file_values.len());
+  println!("succesfully filled {} words", succ_count);
+  if i < succ_count {
+    println!("{} of them carry a value", i);
+  }
+  println!("average bits per word: {}\n", ((num_bytes * 8) as f64 / succ_count as f64));
+
+  println!("\nN-depth summary: {:?}", n_gram_used);
+
+  Ok(text_data)
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn wordify_adaptive(
+  model: &Model,
+  char_to_value_map: &mut HashMap<char, usize>,
+  n: usize,
+  file_contents: &[u8],
+  payload_len: usize,
+  max_bits: usize,
+  use_shuffle: bool,
+  length: Option<LengthTarget>,
+  rng: &mut StdRng,
+) -> Result<String, String> {
+  let continuations = generate_continuation_counts(&model.gram);
+  let mut cursor = Cursor::new(&file_contents);
+  let mut num_bits_remain = file_contents.len() * 8;
+  let mut bitreader = BitReader::endian(&mut cursor, BigEndian);
+  let mut succ_count = 0;
+  let mut bits_used = vec![0; max_bits + 1];
+  let mut n_gram_used = vec![0; n];
+  let mut text_data = String::from("");
+  let mut current_words = if model.context.is_empty() {
+    get_sorted_initial_words(&model.gram, n)
+  } else {
+    get_start_words(model, n)
+  };
+
+  while num_bits_remain > 0 {
+    if let Some(length) = length {
+      let payload_done = file_contents.len() * 8 - num_bits_remain >= payload_len * 8;
+      if payload_done && succ_count >= length.words {
+        break;
+      }
+      if length.exact && succ_count >= length.words {
+        return Err(format!(
+          "The payload needs more than the {} words of --target-words. \
+          Use a bigger --target-words, more --bits or a smaller payload",
+          length.words,
+        ));
+      }
+    }
+    // with a length target, file contents has filler after the payload,
+    // and only as much of it is encoded as it takes to reach the length.
+
+    let num_bits = get_adaptive_bits(&continuations, &current_words, n, max_bits);
+    let num_bits_to_read = if num_bits_remain < num_bits {
+      num_bits_remain
+    } else {
+      num_bits
+    };
+    let mut current_val: u8 = bitreader.read(num_bits_to_read as u32).unwrap();
+    current_val <<= num_bits - num_bits_to_read;
+    // the last value might not have enough bits left in the file,
+    // so pad it with zeros. the decoder drops any bits that do not
+    // make up a full byte.
+
+    let value_mode = utils::ValueMode::CharValueMap(num_bits);
+    let mut usable_words = vec![];
+    if let Some((context_len, _)) = get_adaptive_context_len(&continuations, &current_words, n) {
+      let mut key = current_words[current_words.len() - context_len..].to_vec();
+      for w in &model.unique_words {
+        if utils::is_punctuation(w) {
+          continue;
+        }
+
+        key.push(w);
+        let is_continuation = model.gram.contains_key(&key);
+        key.pop();
+        if is_continuation && utils::get_value_from_chars(w, char_to_value_map, &value_mode) == current_val as usize {
+          usable_words.push(*w);
+        }
+      }
+    }
+    // the words that were seen after the context that decided the
+    // number of bits are the likely ones, so the value is carried by
+    // one of them when possible.
+
+    if usable_words.is_empty() {
+      for w in &model.unique_words {
+        if utils::is_punctuation(w) {
+          continue;
+        }
+
+        let w_val = utils::get_value_from_chars(w, char_to_value_map, &value_mode);
+        if w_val == current_val as usize {
+          usable_words.push(*w);
+        }
+      }
+    }
+    // none of them has the value, so any word of the mimic text
+    // that has it is used instead.
+
+    match usable_words.len() {
+      0 => {
+        return Err(format!("Not enough words with value {} using {} bits", current_val, num_bits));
+      },
+      1 => {
+        let best_word = &usable_words[0];
+        text_data.push_str(best_word);
+        current_words.push(best_word);
+        text_data.push(' ');
+        n_gram_used[0] += 1;
+      },
+      _ => {
+        let (best_word, n_used) = get_best_word(
+          model,
+          &usable_words,
+          &current_words,
+          n,
+        );
+
+        n_gram_used[n_used] += 1;
+        text_data.push_str(best_word);
+        current_words.push(best_word);
+        text_data.push(' ');
       }
     };
 
@@ -571,38 +1185,164 @@ pub fn wordify_from_char_value_mode(
       utils::shuffle_char_value_map(rng, char_to_value_map);
     }
 
-    i += 1;
+    succ_count += 1;
+    bits_used[num_bits] += 1;
+    num_bits_remain -= num_bits_to_read;
   }
 
   text_data.pop(); // remove trailing space
 
-  let num_bytes = (file_values.len() * num_bits) / 8;
+  let num_bytes = payload_len;
   // print summary
-  println!("\nencoding using {} bits per word. file had {} bytes, ie: {} words to wordify", num_bits, num_bytes, file_values.len());
+  println!("\nencoding using up to {} bits per word. file had {} bytes", max_bits, num_bytes);
   println!("succesfully filled {} words", succ_count);
   println!("average bits per word: {}\n", ((num_bytes * 8) as f64 / succ_count as f64));
 
+  println!("\nBits per word summary: {:?}", bits_used);
   println!("\nN-depth summary: {:?}", n_gram_used);
 
   Ok(text_data)
 }
 
+#[allow(clippy::too_many_arguments)]
+pub fn wordify_sentence_acrostic(
+  model: &Model,
+  char_to_value_map: &mut HashMap<char, usize>,
+  n: usize,
+  file_values: Vec<u8>,
+  num_values: usize,
+  num_bits: usize,
+  use_shuffle: bool,
+  value_mode: utils::ValueMode,
+  length: Option<LengthTarget>,
+  rng: &mut StdRng,
+  chat: &Option<chat::Chat>,
+) -> Result<String, String> {
+  let min_sentence_words = 4;
+  let max_sentence_words = 20;
+  let mut word_count = 0;
+  let mut n_gram_used = vec![0; n];
+  let mut text_data = String::from("");
+  let mut current_words = if model.context.is_empty() {
+    vec!["."]
+  } else {
+    get_start_words(model, n)
+  };
+  // every sentence starts right after a period, so use that
+  // as the context instead of get_initial_words. with a template,
+  // the first sentence goes on from the template words instead.
+
+  let followers = generate_followers(&model.gram);
+  let mut free_words = vec![];
+  let mut free_words_no_end = vec![];
+  for w in &model.unique_words {
+    free_words.push(*w);
+    if !utils::is_sentence_end(w) {
+      free_words_no_end.push(*w);
+    }
+  }
+
+  for (i, current_val) in file_values.iter().enumerate() {
+    if i >= num_values && word_count >= length.map_or(0, |l| l.words) {
+      break;
+    }
+    // after the payload, filler sentences are written until
+    // there are enough words.
+
+    let mut usable_words = vec![];
+    for w in &model.unique_words {
+      if utils::is_punctuation(w) {
+        continue;
+      }
+
+      let w_val = utils::get_value_from_chars(w, char_to_value_map, &value_mode);
+      if w_val == *current_val as usize {
+        usable_words.push(*w);
+      }
+    }
+
+    let first_word = match usable_words.len() {
+      0 => return Err(format!("Not enough words starting with a letter of value {}", current_val)),
+      1 => {
+        n_gram_used[0] += 1;
+        usable_words[0]
+      },
+      _ => {
+        let (best_word, n_used) = get_best_word(
+          model,
+          &usable_words,
+          &current_words,
+          n,
+        );
+        n_gram_used[n_used] += 1;
+        best_word
+      },
+    };
+    text_data.push_str(first_word);
+    text_data.push(' ');
+    current_words.push(first_word);
+    word_count += 1;
+
+    let mut sentence_words = 1;
+    loop {
+      let candidates = if sentence_words < min_sentence_words {
+        &free_words_no_end
+      } else {
+        &free_words
+      };
+      let next_word = if sentence_words >= max_sentence_words {
+        "."
+      } else {
+        sample_sentence_word(model, &followers, candidates, &current_words, n, sentence_words >= min_sentence_words)
+      };
+
+      text_data.push_str(next_word);
+      current_words.push(next_word);
+      word_count += 1;
+      sentence_words += 1;
+      if utils::is_sentence_end(next_word) {
+        text_data.push(if chat.is_some() { '\n' } else { ' ' });
+        break;
+      }
+      text_data.push(' ');
+    }
+    // the rest of the sentence does not carry any data, so it is
+    // sampled from the ngrams until the sentence ends. the decoder only
+    // looks at the first word after a sentence end.
+
+    if use_shuffle {
+      utils::shuffle_char_value_map(rng, char_to_value_map);
+    }
+  }
+
+  text_data.pop(); // remove trailing space or newline
+
+  let num_bytes = (num_values * num_bits) / 8;
+  // print summary
+  println!("\nencoding using {} bits per sentence. file had {} bytes, ie: {} sentences to write", num_bits, num_bytes, num_values);
+  println!("wrote {} words in total", word_count);
+  println!("average bits per word: {}\n", ((num_bytes * 8) as f64 / word_count as f64));
+
+  println!("\nN-depth summary: {:?}", n_gram_used);
+
+  Ok(text_data)
+}
+
+#[allow(clippy::too_many_arguments)]
 pub fn encode_char_bit_map(
-  file: &str,
-  output: &str,
+  contents: &[u8],
   seed_str: &str,
-  word_file_name: &str,
+  model: &Model,
   n_depth: usize,
   consecutive_skips: usize,
   depth_skip_threshold: usize,
   num_bits: usize,
   use_shuffle: bool,
-) -> Result<(), String> {
+  length: Option<LengthTarget>,
+  mut chat: Option<chat::Chat>,
+) -> Result<String, String> {
   let mut rng = utils::create_rng_from_seed(seed_str);
   let mut original_rng = utils::create_rng_from_seed(seed_str);
-  let contents = utils::get_file_contents(file)?;
-  let mut word_file_data = utils::get_file_contents_as_string(word_file_name)?;
-
 
   let mut bit_to_char_map = utils::make_bit_to_char_map(num_bits);
   let mut original_bit_to_char_map = bit_to_char_map.clone();
@@ -610,59 +1350,55 @@ pub fn encode_char_bit_map(
   utils::fill_bit_to_char_map(&mut original_rng, &mut original_bit_to_char_map);
 
 
-  let value_vec = get_value_vec(&mut bit_to_char_map, &contents, num_bits, use_shuffle, &mut rng);
+  let value_vec = get_value_vec(&mut bit_to_char_map, contents, num_bits, use_shuffle, &mut rng);
 
 
-  word_file_data = word_file_data.to_lowercase();
-  word_file_data = utils::format_text_for_ngrams(&word_file_data);
-  let (
-    gram_hash,
-    unique_words,
-    total_words,
-  ) = generate_ngrams(&word_file_data, n_depth);
 
 
-  let text_data = wordify(
-    &gram_hash,
+  wordify(
+    model,
     n_depth,
     value_vec,
     &mut original_rng,
     &mut original_bit_to_char_map,
-    &unique_words,
-    total_words as f64,
     consecutive_skips,
     depth_skip_threshold,
     use_shuffle,
-  )?;
-
-  fs::write(output, text_data).unwrap();
-
-  Ok(())
+    length,
+    &mut chat,
+  )
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn encode_char_value_map(
-  file: &str,
-  output: &str,
+  contents: &[u8],
   seed_str: &str,
-  word_file_name: &str,
+  model: &Model,
   n_depth: usize,
-  consecutive_skips: usize,
-  depth_skip_threshold: usize,
+  _consecutive_skips: usize,
+  _depth_skip_threshold: usize,
   num_bits: usize,
   use_shuffle: bool,
   value_mode: utils::ValueMode,
-) -> Result<(), String> {
+  length: Option<LengthTarget>,
+  mut chat: Option<chat::Chat>,
+) -> Result<String, String> {
   let mut rng = utils::create_rng_from_seed(seed_str);
   let mut original_rng = utils::create_rng_from_seed(seed_str);
-  let contents = utils::get_file_contents(file)?;
-  let mut word_file_data = utils::get_file_contents_as_string(word_file_name)?;
 
-  let mut char_to_value_map = utils::make_char_to_value_map(num_bits);
+  let mut char_to_value_map = utils::make_value_map_for_mode(&value_mode, num_bits);
   let mut original_char_to_value_map = char_to_value_map.clone();
   utils::shuffle_char_value_map(&mut rng, &mut char_to_value_map);
   utils::shuffle_char_value_map(&mut original_rng, &mut original_char_to_value_map);
   // panic!("dsa");
 
+  let num_values = (contents.len() * 8).div_ceil(num_bits);
+  let contents = match length {
+    Some(length) => add_filler(contents, seed_str, length.words),
+    None => contents.to_vec(),
+  };
+  // one filler byte per word is always enough, no matter the bits.
+
   let mut value_vec = get_value_vec_from_char_value_mode(
     &contents,
     num_bits,
@@ -672,60 +1408,264 @@ pub fn encode_char_value_map(
   );
 
 
-  word_file_data = word_file_data.to_lowercase();
-  word_file_data = utils::format_text_for_ngrams(&word_file_data);
-  let (
-    gram_hash,
-    unique_words,
-    total_words,
-  ) = generate_ngrams(&word_file_data, n_depth);
 
-  let text_data = wordify_from_char_value_mode(
-    &gram_hash,
-    &mut original_char_to_value_map,
+  match value_mode {
+    utils::ValueMode::SentenceAcrostic(_) => {
+      if length.is_some_and(|l| l.exact) {
+        return Err("--target-words does not work with sentence-acrostic, the sentences have random lengths. Use --min-words".into());
+      }
+      wordify_sentence_acrostic(
+        model,
+        &mut original_char_to_value_map,
+        n_depth,
+        value_vec,
+        num_values,
+        num_bits,
+        use_shuffle,
+        value_mode,
+        length,
+        &mut original_rng,
+        &chat,
+      )
+    },
+    _ => {
+      let positions = get_positions_for_length(&value_mode, seed_str, num_values, length)?;
+      value_vec.truncate(positions.iter().filter(|is_data| **is_data).count());
+      wordify_from_char_value_mode(
+        model,
+        &mut original_char_to_value_map,
+        n_depth,
+        value_vec,
+        &positions,
+        num_bits,
+        use_shuffle,
+        value_mode,
+        &mut original_rng,
+        seed_str.as_bytes(),
+        &mut chat,
+      )
+    },
+  }
+}
+
+
+pub fn encode_adaptive_char_value_map(
+  contents: &[u8],
+  seed_str: &str,
+  model: &Model,
+  n_depth: usize,
+  max_bits: usize,
+  use_shuffle: bool,
+  length: Option<LengthTarget>,
+) -> Result<String, String> {
+  let mut rng = utils::create_rng_from_seed(seed_str);
+
+  let mut char_to_value_map = utils::make_char_to_value_map(max_bits);
+  utils::shuffle_char_value_map(&mut rng, &mut char_to_value_map);
+
+  let padded = match length {
+    Some(length) => add_filler(contents, seed_str, length.words),
+    None => contents.to_vec(),
+  };
+
+  wordify_adaptive(
+    model,
+    &mut char_to_value_map,
     n_depth,
-    value_vec,
-    num_bits,
-    &unique_words,
-    total_words as f64,
+    &padded,
+    contents.len(),
+    max_bits,
     use_shuffle,
-    value_mode,
-    &mut original_rng,
-  )?;
+    length,
+    &mut rng,
+  )
+}
 
-  fs::write(output, text_data).unwrap();
 
-  Ok(())
+#[allow(clippy::too_many_arguments)]
+pub fn encode_to_string(
+  contents: &[u8],
+  seed_str: &str,
+  model: &Model,
+  value_mode: utils::ValueMode,
+  use_shuffle: bool,
+  n_depth: usize,
+  consecutive_skips: usize,
+  depth_skip_threshold: usize,
+  num_bits: usize,
+  length: Option<LengthTarget>,
+  chat: Option<chat::Chat>,
+) -> Result<String, String> {
+  match value_mode {
+    utils::ValueMode::CharBitMap => {
+      encode_char_bit_map(
+        contents,
+        seed_str,
+        model,
+        n_depth,
+        consecutive_skips,
+        depth_skip_threshold,
+        num_bits,
+        use_shuffle,
+        length,
+        chat,
+      )
+    },
+    utils::ValueMode::CharValueMap(_) |
+    utils::ValueMode::WordAcrostic(_) |
+    utils::ValueMode::SentenceAcrostic(_) |
+    utils::ValueMode::WordHash(_) => {
+      encode_char_value_map(
+        contents,
+        seed_str,
+        model,
+        n_depth,
+        consecutive_skips,
+        depth_skip_threshold,
+        num_bits,
+        use_shuffle,
+        value_mode,
+        length,
+        chat,
+      )
+    },
+    utils::ValueMode::AdaptiveCharValue(max_bits) => {
+      if chat.is_some() {
+        return Err("--chat does not work with the adaptive algorithms, the decoder needs every word that came before".into());
+      }
+      encode_adaptive_char_value_map(
+        contents,
+        seed_str,
+        model,
+        n_depth,
+        max_bits,
+        use_shuffle,
+        length,
+      )
+    },
+  }
 }
 
+pub fn require_mimic_algorithm(flag: &str, alg: utils::Algorithm, alg_str: &str) -> Result<(bool, utils::ValueMode), String> {
+  match alg {
+    utils::Algorithm::Shuffle(mode) => Ok((true, mode)),
+    utils::Algorithm::NoShuffle(mode) => Ok((false, mode)),
+    _ => Err(format!("{} needs an algorithm that generates text from the mimic file, not '{}'", flag, alg_str)),
+  }
+  // the cover based algorithms only change a text that already
+  // exists, so options that shape the generated text do not apply.
+}
 
 pub fn encode(matches: &ArgMatches) -> Result<(), String> {
-  let file = utils::get_value(matches, "file")?;
   let output = utils::get_value(matches, "output")?;
-  let mut seed_str = utils::get_value(matches, "seed")?;
-  let password_str = utils::get_value(matches, "password")?;
   let alg_str = utils::get_value(matches, "algorithm")?;
-  let word_file_name = utils::get_value(matches, "words")?;
   let n_depth = utils::get_numerical_value(matches, "n")?;
   let consecutive_skips = utils::get_numerical_value(matches, "consecutive_skips")?;
   let depth_skip_threshold = utils::get_numerical_value(matches, "depth_skip")?;
   let num_bits = utils::get_numerical_value(matches, "bits")?;
 
-  if num_bits > 8 || num_bits < 1 {
+  if !(1..=8).contains(&num_bits) {
     return Err(format!("Bits must be between 1 and 8 inclusively, you provided {}", num_bits));
   }
 
-  let pass;
-  match password_str {
-    "true" => {
-      // get seed string interactively
-      pass = rpassword::prompt_password_stderr("Enter password: ").unwrap();
-      seed_str = pass.as_str();
-    },
-    _ => (),
+  let alg = utils::get_algorithm_from_string(alg_str, num_bits)?;
+  let chat = if matches.is_present("chat") {
+    Some(chat::parse_message_words(utils::get_value(matches, "message_words")?)?)
+  } else {
+    None
+  };
+  if chat.is_some() {
+    require_mimic_algorithm("--chat", alg, alg_str)?;
+  }
+  let split = match matches.value_of("split") {
+    Some(split_str) => Some(split::parse_split(split_str)?),
+    None => None,
   };
 
-  let alg = utils::get_algorithm_from_string(alg_str, num_bits)?;
+  if split.is_some() {
+    if matches.is_present("recipient") {
+      return Err("--split can not be combined with --recipient".into());
+    }
+    require_mimic_algorithm("--split", alg, alg_str)?;
+  }
+  // every share becomes its own generated text, the cover based
+  // algorithms only have one cover to hide in.
+
+  let length = get_length_target(matches)?;
+  if length.is_some() {
+    require_mimic_algorithm("--target-words or --min-words", alg, alg_str)?;
+  }
+
+  let output_format = format::get_output_format(matches)?;
+  if output_format.format != format::OutputFormat::Plain {
+    if chat.is_some() {
+      return Err("--format can not be combined with --chat, every message is already one line".into());
+    }
+    require_mimic_algorithm("--format", alg, alg_str)?;
+  }
+  // the cover based algorithms keep the layout of the cover text,
+  // and some of them hide data in it.
+
+  let template = template::load_template(matches)?;
+  if template.is_some() {
+    if matches.is_present("recipient") {
+      return Err("--template can not be combined with --recipient, the decoder needs the public key header at the start".into());
+    }
+    require_mimic_algorithm("--template", alg, alg_str)?;
+  }
+
+  if matches.is_present("decoy") {
+    if split.is_some() || matches.is_present("recipient") {
+      return Err("--decoy can not be combined with --split or --recipient".into());
+    }
+    require_mimic_algorithm("--decoy", alg, alg_str)?;
+  }
+
+  if let Some(recipient_file) = matches.value_of("recipient") {
+    if secret::get_num_secret_sources(matches) > 0 {
+      return Err("--recipient can not be combined with a seed or password, the seed comes from the key exchange".into());
+    }
+    let (use_shuffle, value_mode) = require_mimic_algorithm("--recipient", alg, alg_str)?;
+
+    let (kind, contents) = archive::get_payload(matches)?;
+    let word_file_data = corpus::load_corpus(matches)?;
+    let model = build_model(matches, &word_file_data, n_depth)?;
+    let text_data = pubkey::encode_to_recipient(
+      &contents,
+      kind,
+      &model,
+      recipient_file,
+      value_mode,
+      use_shuffle,
+      n_depth,
+      consecutive_skips,
+      depth_skip_threshold,
+      num_bits,
+      length,
+      chat,
+    )?;
+    fs::write(output, format::format_text(&text_data, &output_format)).unwrap();
+    return Ok(());
+  }
+
+  let secret = secret::get_secret(matches, "Enter password: ", true)?;
+  let seed_str = secret.as_str();
+  if secret::is_default_seed(matches) {
+    if !matches.is_present("insecure_default_seed") {
+      return Err(format!(
+        "No seed was given, so the default seed '{}' would be used, and anyone could decode the output. \
+        Provide --seed, --pass true, --seed-file, --seed-env or --seed-fd, \
+        or use --insecure-default-seed if you really want the default seed",
+        seed_str,
+      ));
+    }
+    println!("\nWarning: using the default seed '{}'. Anyone can decode this text.\n", seed_str);
+    // the default seed is kept so that old texts can still be
+    // decoded, but it should never be used by accident.
+  } else {
+    password::print_strength(seed_str);
+  }
+  let (kind, contents) = archive::get_payload(matches)?;
 
   let (use_shuffle, value_mode) = match alg {
     utils::Algorithm::Shuffle(mode) => {
@@ -734,35 +1674,136 @@ pub fn encode(matches: &ArgMatches) -> Result<(), String> {
     utils::Algorithm::NoShuffle(mode) => {
       (false, mode)
     },
-  };
-
-  match value_mode {
-    utils::ValueMode::CharBitMap => {
-      encode_char_bit_map(
-        file,
+    utils::Algorithm::Invisible(carrier) => {
+      let cover_file_name = utils::get_value(matches, "cover")?;
+      return invisible::encode_invisible(
+        &contents,
         output,
         seed_str,
-        word_file_name,
-        n_depth,
-        consecutive_skips,
-        depth_skip_threshold,
-        num_bits,
-        use_shuffle,
-      )
+        cover_file_name,
+        carrier,
+        kind,
+      );
     },
-    utils::ValueMode::CharValueMap(val) => {
-      encode_char_value_map(
-        file,
+    utils::Algorithm::Synonym => {
+      let cover_file_name = utils::get_value(matches, "cover")?;
+      let synonym_file_name = utils::get_value(matches, "synonyms")?;
+      return synonym::encode_synonym(
+        &contents,
         output,
         seed_str,
-        word_file_name,
-        n_depth,
-        consecutive_skips,
-        depth_skip_threshold,
-        num_bits,
-        use_shuffle,
-        value_mode,
-      )
+        cover_file_name,
+        synonym_file_name,
+        kind,
+      );
     },
+  };
+
+  let word_file_data = corpus::load_corpus(matches)?;
+  let mut model = build_model(matches, &word_file_data, n_depth)?;
+  if let Some(template) = &template {
+    model.context = template::get_template_context(template, &tokenizer::get_policy(matches)?);
+  }
+
+  if let Some(decoy_file) = matches.value_of("decoy") {
+    let decoy_contents = utils::get_file_contents(decoy_file)?;
+    let decoy_secret = secret::get_decoy_secret(matches)?;
+    if *decoy_secret == *secret {
+      return Err("The decoy seed must be different from the real seed".into());
+    }
+    let text_data = deniable::encode_deniable(
+      &contents,
+      kind,
+      seed_str,
+      &decoy_contents,
+      decoy_secret.as_str(),
+      &model,
+      value_mode,
+      use_shuffle,
+      n_depth,
+      num_bits,
+      length,
+      chat,
+    )?;
+    let text_data = match &template {
+      Some(template) => template::fill_template(template, &text_data),
+      None => text_data,
+    };
+    fs::write(output, format::format_text(&text_data, &output_format)).unwrap();
+    return Ok(());
+  }
+
+  if let Some((threshold, num_shares)) = split {
+    return split::encode_split(
+      &contents,
+      kind,
+      seed_str,
+      &model,
+      output,
+      threshold,
+      num_shares,
+      value_mode,
+      use_shuffle,
+      n_depth,
+      consecutive_skips,
+      depth_skip_threshold,
+      num_bits,
+      length,
+      template.as_ref(),
+      &output_format,
+      chat,
+    );
+  }
+
+  let contents = utils::frame_payload(&contents, seed_str, kind);
+  // every payload gets a header keyed with the seed. the decoder only
+  // keeps what the header says is the payload, so filler and the words
+  // after a template are thrown away, and --find can look for the header.
+
+  let text_data = encode_to_string(
+    &contents,
+    seed_str,
+    &model,
+    value_mode,
+    use_shuffle,
+    n_depth,
+    consecutive_skips,
+    depth_skip_threshold,
+    num_bits,
+    length,
+    chat,
+  )?;
+  if chat.is_some() {
+    println!("wrote {} messages, one per line\n", text_data.lines().count());
+  }
+  let text_data = match &template {
+    Some(template) => template::fill_template(template, &text_data),
+    None => text_data,
+  };
+  fs::write(output, format::format_text(&text_data, &output_format)).unwrap();
+
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn corpus_ngrams_do_not_span_parts() {
+    let text = String::from("a b c d e f");
+    let word_file_data = corpus::Corpus {
+      parts: vec![
+        corpus::CorpusPart { source: "one".into(), start: 0, end: 5, weight: 0.5 },
+        corpus::CorpusPart { source: "two".into(), start: 6, end: text.len(), weight: 0.5 },
+      ],
+      text,
+    };
+    let (gram, unique_words, total_words) = generate_corpus_ngrams(&word_file_data, 2);
+    assert_eq!(unique_words, vec!["a", "b", "c", "d", "e", "f"]);
+    assert_eq!(total_words, 6);
+    assert!(gram.contains_key(&vec!["b", "c"]));
+    assert!(gram.contains_key(&vec!["d", "e"]));
+    assert!(!gram.contains_key(&vec!["c", "d"]));
   }
-}
\ No newline at end of file
+}
@@ -0,0 +1,480 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use clap::ArgMatches;
+use rand::Rng;
+
+use super::utils;
+
+const PARAGRAPH_WORDS: usize = 80;
+const MAX_PARAGRAPH_WORDS: usize = 160;
+const QP_LINE_LENGTH: usize = 76;
+const ENCODED_WORD_LENGTH: usize = 45;
+const MARKDOWN_SPECIAL: [char; 9] = ['\\', '*', '_', '`', '[', ']', '<', '>', '#'];
+const DAYS: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+const MONTHS: [&str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum OutputFormat {
+  Plain,
+  // the text as it was generated, one line per paragraph of the template.
+  Wrap,
+  // lines are broken at the width, nothing else changes.
+  Markdown,
+  // paragraphs separated by blank lines, and characters that
+  // markdown would turn into formatting are escaped.
+  Html,
+  // a whole html document with one <p> per paragraph.
+  Email,
+  // an RFC 5322 email (.eml) with headers, and a quoted-printable body.
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum InputFormat {
+  Plain,
+  Wrap,
+  Markdown,
+  Html,
+  Email,
+  // the output formats, see above.
+  Quoted,
+  // a reply that quotes the text with > in front of every line.
+  Pdf,
+  // text copied out of a pdf, with ligatures, hyphenated line ends
+  // and page numbers.
+  Auto,
+  // the format is guessed from the text.
+}
+
+pub struct FormatOptions {
+  pub format: OutputFormat,
+  pub width: usize,
+  pub subject: Option<String>,
+  pub from: Option<String>,
+  pub to: Option<String>,
+}
+
+pub fn get_output_format_from_string(format_str: &str) -> Result<OutputFormat, String> {
+  match format_str {
+    "plain" => Ok(OutputFormat::Plain),
+    "wrap" => Ok(OutputFormat::Wrap),
+    "markdown" => Ok(OutputFormat::Markdown),
+    "html" => Ok(OutputFormat::Html),
+    "eml" => Ok(OutputFormat::Email),
+    _ => Err(format!("Unknown format '{}', must be one of: plain, wrap, markdown, html, eml", format_str)),
+  }
+}
+
+pub fn get_input_format_from_string(format_str: &str) -> Result<InputFormat, String> {
+  match format_str {
+    "plain" => Ok(InputFormat::Plain),
+    "wrap" => Ok(InputFormat::Wrap),
+    "markdown" => Ok(InputFormat::Markdown),
+    "html" => Ok(InputFormat::Html),
+    "eml" => Ok(InputFormat::Email),
+    "quoted" => Ok(InputFormat::Quoted),
+    "pdf" => Ok(InputFormat::Pdf),
+    "auto" => Ok(InputFormat::Auto),
+    _ => Err(format!("Unknown input format '{}', must be one of: auto, plain, wrap, markdown, html, eml, quoted, pdf", format_str)),
+  }
+  // clap already checks the possible values, this is
+  // for when the string comes from somewhere else.
+}
+
+pub fn get_input_format_name(format: InputFormat) -> &'static str {
+  match format {
+    InputFormat::Plain => "plain",
+    InputFormat::Wrap => "wrap",
+    InputFormat::Markdown => "markdown",
+    InputFormat::Html => "html",
+    InputFormat::Email => "eml",
+    InputFormat::Quoted => "quoted",
+    InputFormat::Pdf => "pdf",
+    InputFormat::Auto => "auto",
+  }
+}
+
+pub fn get_output_format(matches: &ArgMatches) -> Result<FormatOptions, String> {
+  let format = get_output_format_from_string(utils::get_value(matches, "format")?)?;
+  let width = utils::get_numerical_value(matches, "width")?;
+  if width < 20 {
+    return Err("--width must be at least 20".into());
+  }
+  let from = matches.value_of("from").map(String::from);
+  if format == OutputFormat::Email && from.is_none() {
+    return Err("--format eml needs --from, every email must say who it is from".into());
+  }
+
+  Ok(FormatOptions {
+    format,
+    width,
+    subject: matches.value_of("subject").map(String::from),
+    from,
+    to: matches.value_of("to").map(String::from),
+  })
+}
+
+pub fn split_long_line(line: &str) -> Vec<String> {
+  let mut paragraphs = vec![];
+  let mut paragraph: Vec<&str> = vec![];
+  for word in line.split_whitespace() {
+    paragraph.push(word);
+    let at_sentence_end = utils::is_sentence_end(word) && paragraph.len() >= PARAGRAPH_WORDS;
+    if at_sentence_end || paragraph.len() >= MAX_PARAGRAPH_WORDS {
+      paragraphs.push(paragraph.join(" "));
+      paragraph.clear();
+    }
+  }
+  if !paragraph.is_empty() {
+    paragraphs.push(paragraph.join(" "));
+  }
+  // a new paragraph starts after the first sentence end once a
+  // paragraph is long enough. texts without any punctuation, ie: from
+  // char-value, are split after a fixed number of words instead.
+
+  paragraphs
+}
+
+pub fn get_paragraphs(text: &str) -> Vec<String> {
+  let mut paragraphs = vec![];
+  let mut short_lines: Vec<&str> = vec![];
+  for line in text.lines() {
+    let line = line.trim_end();
+    if line.is_empty() || line.split_whitespace().count() > PARAGRAPH_WORDS {
+      if !short_lines.is_empty() {
+        paragraphs.push(short_lines.join("\n"));
+        short_lines.clear();
+      }
+      if !line.is_empty() {
+        paragraphs.extend(split_long_line(line));
+      }
+      continue;
+    }
+    short_lines.push(line);
+  }
+  if !short_lines.is_empty() {
+    paragraphs.push(short_lines.join("\n"));
+  }
+  // lines that are separated by blank lines, ie: from a template,
+  // stay together, and the stegotext, which is one long line, is
+  // split into paragraphs of its own.
+
+  paragraphs
+}
+
+pub fn wrap_line(line: &str, width: usize) -> String {
+  if line.chars().count() <= width {
+    return line.to_string();
+  }
+
+  let mut wrapped = String::new();
+  let mut line_length = 0;
+  for word in line.split_whitespace() {
+    let word_length = word.chars().count();
+    if line_length > 0 && line_length + 1 + word_length > width {
+      wrapped.push('\n');
+      line_length = 0;
+    } else if line_length > 0 {
+      wrapped.push(' ');
+      line_length += 1;
+    }
+    wrapped.push_str(word);
+    line_length += word_length;
+  }
+  // words are never split, a word that is longer than the
+  // width gets a line of its own.
+
+  wrapped
+}
+
+pub fn wrap_text(text: &str, width: usize) -> String {
+  text.lines().map(|line| wrap_line(line, width)).collect::<Vec<String>>().join("\n")
+}
+
+pub fn escape_markdown(text: &str) -> String {
+  let mut escaped = String::with_capacity(text.len());
+  for c in text.chars() {
+    if MARKDOWN_SPECIAL.contains(&c) {
+      escaped.push('\\');
+    }
+    escaped.push(c);
+  }
+
+  escaped
+}
+
+pub fn unescape_markdown(text: &str) -> String {
+  let mut unescaped = String::with_capacity(text.len());
+  let mut chars = text.chars().peekable();
+  while let Some(c) = chars.next() {
+    if c == '\\' && chars.peek().is_some_and(|next| next.is_ascii_punctuation()) {
+      continue;
+    }
+    unescaped.push(c);
+  }
+  // a backslash in front of punctuation only makes markdown
+  // show the punctuation as it is.
+
+  unescaped
+}
+
+pub fn escape_html(text: &str) -> String {
+  text.replace('&', "&amp;")
+    .replace('<', "&lt;")
+    .replace('>', "&gt;")
+    .replace('"', "&quot;")
+}
+
+pub fn to_markdown(text: &str, width: usize) -> String {
+  let paragraphs: Vec<String> = get_paragraphs(text).iter()
+    .map(|paragraph| wrap_text(&escape_markdown(paragraph), width))
+    .collect();
+  let mut markdown = paragraphs.join("\n\n");
+  markdown.push('\n');
+
+  markdown
+}
+
+pub fn to_html(text: &str, width: usize, title: &str) -> String {
+  let mut html = String::from("<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n");
+  html.push_str(&format!("<title>{}</title>\n</head>\n<body>\n", escape_html(title)));
+  for paragraph in get_paragraphs(text) {
+    let lines: Vec<String> = paragraph.lines().map(|line| wrap_line(&escape_html(line), width)).collect();
+    html.push_str(&format!("<p>{}</p>\n", lines.join("<br>\n")));
+  }
+  html.push_str("</body>\n</html>\n");
+  // lines of the same paragraph, ie: the lines of a signature,
+  // are kept apart with <br>, the lines of a wrapped paragraph are not.
+
+  html
+}
+
+pub fn format_date(seconds: u64) -> String {
+  let days = seconds / 86400;
+  let time = seconds % 86400;
+
+  let z = days as i64 + 719_468;
+  let era = z.div_euclid(146_097);
+  let day_of_era = z.rem_euclid(146_097);
+  let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+  let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+  let month_index = (5 * day_of_year + 2) / 153;
+  let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+  let month = if month_index < 10 { month_index + 3 } else { month_index - 9 };
+  let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+  // days since 1970 to a date in the gregorian calendar, see
+  // http://howardhinnant.github.io/date_algorithms.html
+
+  format!(
+    "{}, {:02} {} {} {:02}:{:02}:{:02} +0000",
+    DAYS[(days % 7) as usize],
+    day,
+    MONTHS[(month - 1) as usize],
+    year,
+    time / 3600,
+    time % 3600 / 60,
+    time % 60,
+  )
+}
+
+pub fn encode_quoted_printable(text: &str) -> String {
+  let mut encoded = String::new();
+  for line in text.lines() {
+    let bytes = line.as_bytes();
+    let mut line_length = 0;
+    for (i, byte) in bytes.iter().enumerate() {
+      let is_last = i == bytes.len() - 1;
+      let literal = ((*byte == b' ' || *byte == b'\t') && !is_last)
+        || ((33..=126).contains(byte) && *byte != b'=');
+      let chunk = if literal { (*byte as char).to_string() } else { format!("={:02X}", byte) };
+
+      if line_length + chunk.len() > QP_LINE_LENGTH - 1 {
+        encoded.push_str("=\r\n");
+        line_length = 0;
+      }
+      encoded.push_str(&chunk);
+      line_length += chunk.len();
+    }
+    encoded.push_str("\r\n");
+  }
+  // RFC 2045: lines of at most 76 characters, longer lines are broken
+  // with a soft line break (= at the end), and spaces at the end of a
+  // line, = and anything that is not ascii are written as =XX.
+
+  encoded
+}
+
+pub fn decode_quoted_printable(text: &str) -> String {
+  let bytes = text.as_bytes();
+  let mut decoded = vec![];
+  let mut i = 0;
+  while i < bytes.len() {
+    if bytes[i] != b'=' {
+      decoded.push(bytes[i]);
+      i += 1;
+      continue;
+    }
+
+    let rest = &bytes[i + 1..];
+    if rest.starts_with(b"\r\n") {
+      i += 3;
+    } else if rest.starts_with(b"\n") {
+      i += 2;
+    } else if let Some(byte) = std::str::from_utf8(&rest[..rest.len().min(2)]).ok().and_then(|hex| u8::from_str_radix(hex, 16).ok()) {
+      decoded.push(byte);
+      i += 3;
+    } else {
+      decoded.push(b'=');
+      i += 1;
+    }
+  }
+  // soft line breaks are removed, so the words that were split
+  // across lines are joined again.
+
+  String::from_utf8_lossy(&decoded).into_owned()
+}
+
+pub fn encode_header_word(text: &str) -> String {
+  let mut words = vec![];
+  let mut word = String::new();
+  for c in text.chars() {
+    let mut bytes = [0u8; 4];
+    let chunk: String = if c.is_ascii_alphanumeric() || "!*+-/".contains(c) {
+      c.to_string()
+    } else if c == ' ' {
+      String::from("_")
+    } else {
+      c.encode_utf8(&mut bytes).bytes().map(|byte| format!("={:02X}", byte)).collect()
+    };
+
+    if !word.is_empty() && word.len() + chunk.len() > ENCODED_WORD_LENGTH {
+      words.push(format!("=?UTF-8?Q?{}?=", word));
+      word.clear();
+    }
+    word.push_str(&chunk);
+  }
+  if !word.is_empty() {
+    words.push(format!("=?UTF-8?Q?{}?=", word));
+  }
+  // RFC 2047: an encoded word is at most 75 characters and only holds
+  // whole characters, so a long value is split into several words.
+  // the folding whitespace between them is ignored when reading it.
+
+  words.join("\r\n ")
+}
+
+pub fn encode_header_value(value: &str) -> String {
+  if value.chars().all(|c| c == ' ' || c.is_ascii_graphic()) {
+    return value.to_string();
+  }
+
+  match value.rfind('<') {
+    Some(start) if value.ends_with('>') => {
+      let name = value[..start].trim_end();
+      if name.is_empty() {
+        value.to_string()
+      } else {
+        format!("{} {}", encode_header_word(name), &value[start..])
+      }
+    },
+    _ => encode_header_word(value),
+  }
+  // headers can only hold ascii. in an address like Zoë <zoe@example.com>
+  // only the name is encoded, the address has to stay as it is.
+}
+
+pub fn to_email(text: &str, output_format: &FormatOptions) -> String {
+  let seconds = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+  let from = output_format.from.as_deref().unwrap_or("");
+  let domain = from.rsplit('@').next().unwrap_or("localhost").trim_end_matches('>');
+  let message_id: String = (0..16).map(|_| format!("{:02x}", rand::thread_rng().gen::<u8>())).collect();
+
+  let mut headers = vec![
+    format!("From: {}", encode_header_value(from)),
+    format!("Date: {}", format_date(seconds)),
+    format!("Message-ID: <{}@{}>", message_id, domain),
+  ];
+  if let Some(to) = &output_format.to {
+    headers.push(format!("To: {}", encode_header_value(to)));
+  }
+  if let Some(subject) = &output_format.subject {
+    headers.push(format!("Subject: {}", encode_header_value(subject)));
+  }
+  headers.push(String::from("MIME-Version: 1.0"));
+  headers.push(String::from("Content-Type: text/plain; charset=utf-8"));
+  headers.push(String::from("Content-Transfer-Encoding: quoted-printable"));
+
+  let body: Vec<String> = get_paragraphs(text).iter().map(|paragraph| wrap_text(paragraph, output_format.width)).collect();
+  format!("{}\r\n\r\n{}", headers.join("\r\n"), encode_quoted_printable(&body.join("\n\n")))
+}
+
+pub fn format_text(text: &str, output_format: &FormatOptions) -> String {
+  match output_format.format {
+    OutputFormat::Plain => text.to_string(),
+    OutputFormat::Wrap => wrap_text(text, output_format.width),
+    OutputFormat::Markdown => to_markdown(text, output_format.width),
+    OutputFormat::Html => to_html(text, output_format.width, output_format.subject.as_deref().unwrap_or("")),
+    OutputFormat::Email => to_email(text, output_format),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn quoted_printable_escapes() {
+    assert_eq!(encode_quoted_printable("a=b"), "a=3Db\r\n");
+    assert_eq!(encode_quoted_printable("ends with space \nand tab\t"), "ends with space=20\r\nand tab=09\r\n");
+    assert_eq!(encode_quoted_printable("caf\u{e9}"), "caf=C3=A9\r\n");
+    assert_eq!(encode_quoted_printable("in the middle"), "in the middle\r\n");
+  }
+
+  #[test]
+  fn quoted_printable_soft_line_breaks() {
+    let line = "word ".repeat(40);
+    let encoded = encode_quoted_printable(line.trim_end());
+    assert!(encoded.contains("=\r\n"));
+    assert!(encoded.split("\r\n").all(|l| l.len() <= QP_LINE_LENGTH));
+    assert_eq!(decode_quoted_printable(&encoded).trim_end(), line.trim_end());
+
+    let escapes = "=C3=A9".repeat(30);
+    let encoded = encode_quoted_printable(&"\u{e9}".repeat(30));
+    assert!(encoded.split("\r\n").all(|l| l.len() <= QP_LINE_LENGTH));
+    assert_eq!(encoded.replace("=\r\n", "").trim_end(), escapes);
+  }
+
+  #[test]
+  fn quoted_printable_round_trips() {
+    let text = "plain, = signs, caf\u{e9}, trailing space \nand a tab\t\nlast line";
+    assert_eq!(decode_quoted_printable(&encode_quoted_printable(text)).replace("\r\n", "\n").trim_end(), text);
+  }
+
+  #[test]
+  fn quoted_printable_decodes_loose_input() {
+    assert_eq!(decode_quoted_printable("soft=\nbreak"), "softbreak");
+    assert_eq!(decode_quoted_printable("soft=\r\nbreak"), "softbreak");
+    assert_eq!(decode_quoted_printable("a=3db"), "a=b");
+    assert_eq!(decode_quoted_printable("not =ZZ hex"), "not =ZZ hex");
+    assert_eq!(decode_quoted_printable("ends with ="), "ends with =");
+  }
+
+  #[test]
+  fn header_values_are_encoded_words() {
+    assert_eq!(encode_header_value("Alice <alice@example.com>"), "Alice <alice@example.com>");
+    assert_eq!(encode_header_value("Zo\u{eb} <zoe@example.com>"), "=?UTF-8?Q?Zo=C3=AB?= <zoe@example.com>");
+    assert_eq!(encode_header_value("Caf\u{e9} au lait"), "=?UTF-8?Q?Caf=C3=A9_au_lait?=");
+    assert_eq!(encode_header_value("a_b=c?"), "a_b=c?");
+    assert_eq!(encode_header_value("\u{e9}_=?"), "=?UTF-8?Q?=C3=A9=5F=3D=3F?=");
+
+    let long = encode_header_value(&"\u{e9}t\u{e9} ".repeat(10));
+    let words: Vec<&str> = long.split("\r\n ").collect();
+    assert!(words.len() > 1);
+    for word in words {
+      assert!(word.starts_with("=?UTF-8?Q?") && word.ends_with("?="));
+      assert!(word.len() <= 75);
+      assert!(!word.contains(' '));
+      let encoded = &word["=?UTF-8?Q?".len()..word.len() - 2];
+      assert!(!decode_quoted_printable(encoded).contains('\u{fffd}'));
+    }
+  }
+}
@@ -0,0 +1,311 @@
+use std::fs;
+use std::io::Cursor;
+
+use rand::prelude::StdRng;
+use bitstream_io::{BigEndian, BitReader, BitWriter};
+
+use super::utils;
+use super::utils::Carrier;
+
+const ZERO_WIDTH_CHARS: [char; 4] = ['\u{200B}', '\u{200C}', '\u{200D}', '\u{2060}'];
+const NUM_VARIATION_SELECTORS: u32 = 8;
+const TRAILING_WHITESPACE_CHARS: [char; 2] = [' ', '\t'];
+const HOMOGLYPHS: [(char, char); 18] = [
+  ('a', '\u{0430}'), ('c', '\u{0441}'), ('e', '\u{0435}'), ('o', '\u{043E}'),
+  ('p', '\u{0440}'), ('x', '\u{0445}'), ('y', '\u{0443}'), ('A', '\u{0410}'),
+  ('B', '\u{0412}'), ('C', '\u{0421}'), ('E', '\u{0415}'), ('H', '\u{041D}'),
+  ('K', '\u{041A}'), ('M', '\u{041C}'), ('O', '\u{041E}'), ('P', '\u{0420}'),
+  ('T', '\u{0422}'), ('X', '\u{0425}'),
+];
+
+pub fn get_carrier_name(carrier: &Carrier) -> &'static str {
+  match carrier {
+    Carrier::ZeroWidth => "zero width characters",
+    Carrier::VariationSelector => "variation selectors",
+    Carrier::Homoglyph => "homoglyphs",
+    Carrier::TrailingWhitespace => "trailing whitespace",
+  }
+}
+
+pub fn get_strip_warnings(carrier: &Carrier) -> Vec<&'static str> {
+  match carrier {
+    Carrier::ZeroWidth => vec![
+      "HTML sanitizers used by many forums, comment sections and CMS editors",
+      "chat and social media apps that clean up invisible characters in messages",
+      "copying the text through plain text tools that drop format characters",
+    ],
+    Carrier::VariationSelector => vec![
+      "HTML sanitizers used by many forums, comment sections and CMS editors",
+      "chat and social media apps that clean up invisible characters in messages",
+      "fonts and editors that normalize emoji presentation",
+    ],
+    Carrier::Homoglyph => vec![
+      "spam filters and link checkers that replace or flag mixed scripts",
+      "spell checkers, which will underline every swapped word",
+      "anyone who retypes the text or searches it for a swapped word",
+    ],
+    Carrier::TrailingWhitespace => vec![
+      "text and code editors that trim trailing whitespace on save",
+      "HTML, Markdown and chat apps, which collapse or trim whitespace",
+      "email clients that re-wrap lines or use format=flowed",
+    ],
+  }
+}
+
+pub fn print_strip_warnings(carrier: &Carrier) {
+  println!("Warning: {} are known to be removed by:", get_carrier_name(carrier));
+  for warning in get_strip_warnings(carrier) {
+    println!("  - {}", warning);
+  }
+  println!("Make sure the channel you post this text to keeps them intact.");
+}
+
+pub fn get_carrier_alphabet(carrier: &Carrier) -> Vec<char> {
+  match carrier {
+    Carrier::ZeroWidth => ZERO_WIDTH_CHARS.to_vec(),
+    Carrier::VariationSelector => (0..NUM_VARIATION_SELECTORS).map(|i| std::char::from_u32(0xFE00 + i).unwrap()).collect(),
+    // only VS1 to VS8. VS15 and VS16 pick the text or emoji look of
+    // an emoji, so covers are full of them.
+    Carrier::TrailingWhitespace => TRAILING_WHITESPACE_CHARS.to_vec(),
+    Carrier::Homoglyph => vec![],
+    // homoglyphs are not inserted, they replace a letter that is
+    // already there. see get_homoglyph_pair
+  }
+}
+
+pub fn get_homoglyph_pair(c: char) -> Option<(char, char)> {
+  for (latin, cyrillic) in HOMOGLYPHS.iter() {
+    if c == *latin || c == *cyrillic {
+      return Some((*latin, *cyrillic));
+    }
+  }
+
+  None
+}
+
+pub fn get_alphabet_bits(alphabet: &[char]) -> usize {
+  utils::get_bits_for_candidates(alphabet.len(), 8)
+}
+
+pub fn get_keyed_alphabet(rng: &mut StdRng, alphabet: &[char]) -> Vec<char> {
+  let mut ordered = alphabet.to_vec();
+  utils::shuffle_in_place(rng, &mut ordered);
+  // same idea as shuffling the bit to char map: every symbol gets
+  // its own order, so the pattern of carrier characters depends on
+  // the seed and repeated values do not repeat the same character.
+
+  ordered
+}
+
+pub fn get_insert_positions(text: &str, carrier: &Carrier) -> Vec<usize> {
+  match carrier {
+    Carrier::TrailingWhitespace => {
+      let mut positions = vec![];
+      for (i, c) in text.char_indices() {
+        if c == '\n' {
+          if i > 0 && text[..i].ends_with('\r') {
+            positions.push(i - 1);
+          } else {
+            positions.push(i);
+          }
+        }
+      }
+      positions.push(text.len());
+      positions
+    },
+    _ => utils::get_word_spans(text).iter().map(|(_, end)| *end).collect(),
+  }
+}
+
+pub fn clean_cover_text(text: &str, carrier: &Carrier) -> Result<String, String> {
+  let alphabet = get_carrier_alphabet(carrier);
+  let found = match carrier {
+    Carrier::TrailingWhitespace => {
+      let mut num_lines = 0;
+      let lines: Vec<String> = text.split('\n').map(|line| {
+        let has_carriage_return = line.ends_with('\r');
+        let line = line.trim_end_matches('\r');
+        let trimmed = line.trim_end_matches(|c| alphabet.contains(&c));
+        if trimmed.len() < line.len() {
+          num_lines += 1;
+        }
+        if has_carriage_return {
+          [trimmed, "\r"].join("")
+        } else {
+          trimmed.to_string()
+        }
+      }).collect();
+      if num_lines > 0 {
+        println!("Warning: removed the trailing whitespace of {} lines of the cover text", num_lines);
+      }
+      return Ok(lines.join("\n"));
+      // trailing whitespace does not change how the text looks, so it
+      // is safe to remove.
+    },
+    Carrier::Homoglyph => text.chars().filter(|c| HOMOGLYPHS.iter().any(|(_, cyrillic)| cyrillic == c)).count(),
+    _ => text.chars().filter(|c| alphabet.contains(c)).count(),
+  };
+  if found > 0 {
+    return Err(format!(
+      "The cover text already has {} {}, the decoder would read them as data. Remove them or use another algorithm",
+      found,
+      get_carrier_name(carrier),
+    ));
+  }
+  // removing them would break the text, like the joiners in emoji
+  // sequences or words written in cyrillic.
+
+  Ok(text.to_string())
+}
+
+pub fn encode_invisible(
+  contents: &[u8],
+  output: &str,
+  seed_str: &str,
+  cover_file_name: &str,
+  carrier: Carrier,
+  kind: utils::PayloadKind,
+) -> Result<(), String> {
+  let mut rng = utils::create_rng_from_seed(seed_str);
+  let cover_text = clean_cover_text(&utils::get_file_contents_as_string(cover_file_name)?, &carrier)?;
+
+  let framed_contents = utils::frame_payload(contents, seed_str, kind);
+  let mut cursor = Cursor::new(&framed_contents);
+  let mut num_bits_remain = framed_contents.len() * 8;
+  let mut bitreader = BitReader::endian(&mut cursor, BigEndian);
+  let mut text_data = String::from("");
+
+  if let Carrier::Homoglyph = carrier {
+    let mut swap_count = 0;
+    for c in cover_text.chars() {
+      let pair = match get_homoglyph_pair(c) {
+        Some(pair) if num_bits_remain > 0 => pair,
+        _ => {
+          text_data.push(c);
+          continue;
+        },
+      };
+
+      let ordered = get_keyed_alphabet(&mut rng, &[pair.0, pair.1]);
+      let value: u8 = bitreader.read(1).unwrap();
+      let chosen = ordered[value as usize];
+      if chosen != pair.0 {
+        swap_count += 1;
+      }
+      text_data.push(chosen);
+      num_bits_remain -= 1;
+    }
+
+    if num_bits_remain > 0 {
+      return Err(format!(
+        "Cover text is too small: it ran out of letters with {} of {} bits left to encode",
+        num_bits_remain,
+        framed_contents.len() * 8,
+      ));
+    }
+
+    println!("\nencoded {} bytes by swapping {} letters for homoglyphs\n", contents.len(), swap_count);
+  } else {
+    let alphabet = get_carrier_alphabet(&carrier);
+    let num_bits = get_alphabet_bits(&alphabet);
+    let mut symbols = vec![];
+    while num_bits_remain > 0 {
+      let num_bits_to_read = if num_bits_remain < num_bits {
+        num_bits_remain
+      } else {
+        num_bits
+      };
+      let mut value: u8 = bitreader.read(num_bits_to_read as u32).unwrap();
+      value <<= num_bits - num_bits_to_read;
+
+      let ordered = get_keyed_alphabet(&mut rng, &alphabet);
+      symbols.push(ordered[value as usize]);
+      num_bits_remain -= num_bits_to_read;
+    }
+
+    let positions = get_insert_positions(&cover_text, &carrier);
+    if positions.is_empty() {
+      return Err("Cover text has no positions, it needs at least one word".into());
+    }
+    let symbols_per_position = symbols.len().div_ceil(positions.len());
+    // spread the symbols out over the whole cover text instead of
+    // putting all of them in one place.
+    let mut last_end = 0;
+    let mut symbol_chunks = symbols.chunks(symbols_per_position);
+    for position in &positions {
+      let chunk = match symbol_chunks.next() {
+        Some(chunk) => chunk,
+        None => break,
+      };
+      text_data.push_str(&cover_text[last_end..*position]);
+      text_data.extend(chunk);
+      last_end = *position;
+    }
+    text_data.push_str(&cover_text[last_end..]);
+
+    println!(
+      "\nencoded {} bytes into {} {} at {} positions\n",
+      contents.len(),
+      symbols.len(),
+      get_carrier_name(&carrier),
+      positions.len().min(symbols.len()),
+    );
+  }
+
+  print_strip_warnings(&carrier);
+  fs::write(output, text_data).unwrap();
+
+  Ok(())
+}
+
+pub fn get_carrier_symbols(text: &str, carrier: &Carrier) -> Vec<char> {
+  let alphabet = get_carrier_alphabet(carrier);
+  match carrier {
+    Carrier::TrailingWhitespace => {
+      let mut symbols = vec![];
+      for line in text.split('\n') {
+        let line = line.trim_end_matches('\r');
+        let trimmed = line.trim_end_matches(|c| alphabet.contains(&c));
+        symbols.extend(line[trimmed.len()..].chars());
+      }
+      symbols
+    },
+    Carrier::Homoglyph => text.chars().filter(|c| get_homoglyph_pair(*c).is_some()).collect(),
+    _ => text.chars().filter(|c| alphabet.contains(c)).collect(),
+  }
+}
+
+pub fn get_invisible_payload(
+  contents: &str,
+  seed_str: &str,
+  carrier: &Carrier,
+) -> Result<Vec<u8>, String> {
+  let mut rng = utils::create_rng_from_seed(seed_str);
+  let symbols = get_carrier_symbols(contents, carrier);
+
+  let mut bitwriter = BitWriter::endian(Vec::new(), BigEndian);
+  for symbol in &symbols {
+    let (ordered, num_bits) = match carrier {
+      Carrier::Homoglyph => {
+        let pair = get_homoglyph_pair(*symbol).unwrap();
+        (get_keyed_alphabet(&mut rng, &[pair.0, pair.1]), 1)
+      },
+      _ => {
+        let alphabet = get_carrier_alphabet(carrier);
+        let num_bits = get_alphabet_bits(&alphabet);
+        (get_keyed_alphabet(&mut rng, &alphabet), num_bits)
+      },
+    };
+
+    let value = ordered.iter().position(|c| c == symbol).unwrap();
+    bitwriter.write(num_bits as u32, value as u8).unwrap();
+  }
+
+  Ok(bitwriter.into_writer())
+}
+
+pub fn print_decode_failure(contents: &str, carrier: &Carrier) {
+  let symbols = get_carrier_symbols(contents, carrier);
+  println!("Found {} {} in the text.", symbols.len(), get_carrier_name(carrier));
+  print_strip_warnings(carrier);
+}
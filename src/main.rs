@@ -1,10 +1,33 @@
 use clap::{App, load_yaml};
 
+mod adapter;
+mod analyze;
+mod archive;
+mod audit;
+mod chat;
+mod corpus;
 mod decode;
+mod deniable;
+mod detect;
 mod encode;
+mod format;
+mod invisible;
+mod password;
+mod pos;
+mod pubkey;
+mod secret;
+mod split;
+mod synonym;
+mod template;
+mod tokenizer;
+mod topic;
 mod utils;
+mod vocabulary;
 
+use analyze::analyze;
+use audit::audit;
 use decode::decode;
+use detect::detect;
 use encode::encode;
 
 fn main() {
@@ -15,12 +38,21 @@ fn main() {
       encode(encode_matches)
     } else if let Some(decode_matches) = matches.subcommand_matches("decode") {
       decode(decode_matches)
+    } else if let Some(analyze_matches) = matches.subcommand_matches("analyze") {
+      analyze(analyze_matches)
+    } else if let Some(detect_matches) = matches.subcommand_matches("detect") {
+      detect(detect_matches)
+    } else if let Some(audit_matches) = matches.subcommand_matches("audit") {
+      audit(audit_matches)
+    } else if let Some(keygen_matches) = matches.subcommand_matches("keygen") {
+      pubkey::keygen(keygen_matches)
+    } else if let Some(pubkey_matches) = matches.subcommand_matches("pubkey") {
+      pubkey::pubkey(pubkey_matches)
     } else {
-      panic!("Must provide command: either 'encode' or 'decode'");
+      panic!("Must provide command: either 'encode', 'decode', 'analyze', 'detect', 'audit', 'keygen' or 'pubkey'");
     };
 
-    match result {
-      Err(e) => println!("Error: {}", e),
-      _ => (),
+    if let Err(e) = result {
+      println!("Error: {}", e);
     }
 }
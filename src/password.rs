@@ -0,0 +1,174 @@
+const COMMON_PASSWORDS: [&str; 60] = [
+  "123456", "password", "123456789", "12345678", "12345", "qwerty", "abc123",
+  "football", "1234567", "monkey", "111111", "letmein", "1234", "1234567890",
+  "dragon", "baseball", "sunshine", "iloveyou", "trustno1", "princess",
+  "adobe123", "123123", "welcome", "login", "admin", "qwerty123", "solo",
+  "1q2w3e4r", "master", "666666", "photoshop", "1qaz2wsx", "qwertyuiop",
+  "ashley", "mustang", "121212", "starwars", "654321", "bailey", "access",
+  "flower", "555555", "passw0rd", "shadow", "lovely", "7777777", "michael",
+  "jesus", "password1", "superman", "hello", "charlie", "888888", "696969",
+  "hottie", "freedom", "aa123456", "qazwsx", "ninja", "secret",
+];
+const KEYBOARD_ROWS: [&str; 4] = ["1234567890", "qwertyuiop", "asdfghjkl", "zxcvbnm"];
+const SEQUENCES: [&str; 2] = ["abcdefghijklmnopqrstuvwxyz", "01234567890"];
+const LEET_SUBSTITUTIONS: [(char, char); 8] = [
+  ('4', 'a'), ('@', 'a'), ('3', 'e'), ('1', 'i'), ('!', 'i'), ('0', 'o'), ('$', 's'), ('5', 's'),
+];
+const BRUTEFORCE_CARDINALITY: f64 = 10.0;
+const GUESSES_PER_SECOND_ONLINE: f64 = 10.0;
+const GUESSES_PER_SECOND_OFFLINE: f64 = 1e10;
+
+pub struct Strength {
+  pub guesses: f64,
+  pub score: usize,
+  pub reason: String,
+}
+
+pub fn unleet(password: &str) -> String {
+  password.to_lowercase().chars().map(|c| {
+    match LEET_SUBSTITUTIONS.iter().find(|(from, _)| *from == c) {
+      Some((_, to)) => *to,
+      None => c,
+    }
+  }).collect()
+}
+
+pub fn get_common_rank(word: &str) -> Option<usize> {
+  COMMON_PASSWORDS.iter().position(|p| *p == word).map(|i| i + 1)
+}
+
+pub fn is_run_of(password: &str, alphabets: &[&str]) -> bool {
+  let lower = password.to_lowercase();
+  if lower.chars().count() < 3 {
+    return false;
+  }
+
+  alphabets.iter().any(|alphabet| {
+    let reversed: String = alphabet.chars().rev().collect();
+    alphabet.contains(&lower) || reversed.contains(&lower)
+  })
+}
+
+pub fn is_repeat(password: &str) -> bool {
+  let chars: Vec<char> = password.chars().collect();
+  for unit in 1..=chars.len() / 2 {
+    if chars.len() % unit == 0 && chars.chunks(unit).all(|chunk| chunk == &chars[..unit]) {
+      return true;
+    }
+  }
+
+  false
+}
+
+pub fn get_charset_size(password: &str) -> f64 {
+  let mut size = 0.0;
+  if password.chars().any(|c| c.is_ascii_lowercase()) {
+    size += 26.0;
+  }
+  if password.chars().any(|c| c.is_ascii_uppercase()) {
+    size += 26.0;
+  }
+  if password.chars().any(|c| c.is_ascii_digit()) {
+    size += 10.0;
+  }
+  if password.chars().any(|c| c.is_ascii_punctuation() || c == ' ') {
+    size += 33.0;
+  }
+  if !password.is_ascii() {
+    size += 100.0;
+  }
+
+  size
+}
+
+pub fn estimate_strength(password: &str) -> Strength {
+  let length = password.chars().count();
+  let mut guesses = BRUTEFORCE_CARDINALITY.powi(length as i32);
+  let mut reason = String::from("no common pattern found");
+  // like zxcvbn, brute force counts 10 guesses per character instead of
+  // the full character set. passwords picked by people are far from
+  // random, so the full character set would overestimate them.
+
+  let mut candidates = vec![];
+  let base = password.trim_end_matches(|c: char| c.is_ascii_digit() || c.is_ascii_punctuation());
+  let suffix_len = length - base.chars().count();
+  for (word, extra) in [(password, 0), (base, suffix_len)].iter() {
+    let unleeted = unleet(word);
+    let variations = if unleeted != word.to_lowercase() || *word != word.to_lowercase() { 20.0 } else { 1.0 };
+    let suffix_guesses = 20f64.powi(*extra as i32);
+    if let Some(rank) = get_common_rank(&unleeted) {
+      candidates.push((rank as f64 * variations * suffix_guesses, "is a very common password"));
+    }
+    if is_run_of(word, &KEYBOARD_ROWS) {
+      candidates.push((word.len() as f64 * 8.0 * variations * suffix_guesses, "is a row of keys on the keyboard"));
+    }
+    if is_run_of(word, &SEQUENCES) {
+      candidates.push((word.len() as f64 * 4.0 * variations * suffix_guesses, "is a simple sequence"));
+    }
+    if !word.is_empty() && is_repeat(word) {
+      candidates.push((get_charset_size(word) * word.len() as f64 * suffix_guesses, "repeats the same characters"));
+    }
+  }
+  // a cut down version of what zxcvbn does: check if the password (or
+  // the password without digits and symbols at the end) is something
+  // attackers try first, and count how many guesses that takes. common
+  // substitutions like 4 for a and capital letters only add a few guesses.
+
+  for (candidate_guesses, candidate_reason) in candidates {
+    if candidate_guesses < guesses {
+      guesses = candidate_guesses;
+      reason = String::from(candidate_reason);
+    }
+  }
+  guesses = guesses.max(1.0);
+
+  let score = match guesses {
+    g if g < 1e3 => 0,
+    g if g < 1e6 => 1,
+    g if g < 1e8 => 2,
+    g if g < 1e10 => 3,
+    _ => 4,
+  };
+
+  Strength { guesses, score, reason }
+}
+
+pub fn format_duration(seconds: f64) -> String {
+  let units = [
+    (60.0 * 60.0 * 24.0 * 365.0 * 100.0, "centuries"),
+    (60.0 * 60.0 * 24.0 * 365.0, "years"),
+    (60.0 * 60.0 * 24.0 * 30.0, "months"),
+    (60.0 * 60.0 * 24.0, "days"),
+    (60.0 * 60.0, "hours"),
+    (60.0, "minutes"),
+    (1.0, "seconds"),
+  ];
+  if seconds < 1.0 {
+    return String::from("less than a second");
+  }
+  for (unit_seconds, name) in units.iter() {
+    if seconds >= *unit_seconds {
+      return format!("{:.0} {}", (seconds / unit_seconds).floor(), name);
+    }
+  }
+
+  String::from("less than a second")
+}
+
+pub fn print_strength(password: &str) {
+  let strength = estimate_strength(password);
+  println!(
+    "\npassword strength: {}/4 (about 10^{:.0} guesses, {})",
+    strength.score,
+    strength.guesses.log10(),
+    strength.reason,
+  );
+
+  if strength.score < 3 {
+    println!("Warning: this password is weak. Time to guess it:");
+    println!("  online, {} guesses per second: {}", GUESSES_PER_SECOND_ONLINE, format_duration(strength.guesses / GUESSES_PER_SECOND_ONLINE));
+    println!("  offline, 10 billion guesses per second: {}", format_duration(strength.guesses / GUESSES_PER_SECOND_OFFLINE));
+    println!("Anyone who has the stegotext can try passwords offline, as fast as their hardware allows.");
+  }
+  println!();
+}
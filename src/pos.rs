@@ -0,0 +1,223 @@
+use std::collections::HashMap;
+
+use clap::ArgMatches;
+
+use super::utils;
+
+pub const TAGS: [&str; 11] = ["DET", "PRON", "NOUN", "VERB", "AUX", "ADJ", "ADV", "ADP", "CONJ", "NUM", "PUNCT"];
+const DET: usize = 0;
+const PRON: usize = 1;
+const NOUN: usize = 2;
+const VERB: usize = 3;
+const AUX: usize = 4;
+const ADJ: usize = 5;
+const ADV: usize = 6;
+const ADP: usize = 7;
+const CONJ: usize = 8;
+const NUM: usize = 9;
+const PUNCT: usize = 10;
+const CLAUSE_WINDOW: usize = 4;
+
+const LEXICON: [(usize, &str); 9] = [
+  (DET, "the a an this that these those some any each every no my your his her its our their all both either neither another such"),
+  (PRON, "i you he she it we they me him us them myself yourself himself herself itself ourselves themselves \
+    who whom whose what which something nothing anything everything someone anyone everyone nobody none"),
+  (AUX, "is are was were be been being am has have had do does did will would shall should can could may might must \
+    isn't aren't wasn't weren't hasn't haven't hadn't don't doesn't didn't won't wouldn't shouldn't can't couldn't"),
+  (ADP, "of in on at by for with from to into onto upon about over under after before between through during \
+    without within against among across behind beyond near off out up down around toward towards since until than like"),
+  (CONJ, "and or but nor so yet because although though while if unless whether when where as then"),
+  (ADV, "not very too also just only even still already never always often sometimes here there now again soon \
+    quite rather almost perhaps yes"),
+  (NUM, "one two three four five six seven eight nine ten eleven twelve twenty thirty hundred thousand million"),
+  (VERB, "said says say go goes went gone come came see saw seen know knew known think thought take took taken \
+    make made get got give gave given find found tell told ask asked feel felt seem seemed leave left"),
+  (ADJ, "good bad new old great little long own other same few many much more most last first next"),
+];
+const SUFFIXES: [(&str, usize); 19] = [
+  ("ly", ADV), ("ing", VERB), ("ed", VERB), ("ize", VERB), ("ise", VERB),
+  ("tion", NOUN), ("sion", NOUN), ("ment", NOUN), ("ness", NOUN), ("ity", NOUN), ("ship", NOUN), ("ism", NOUN),
+  ("ous", ADJ), ("ful", ADJ), ("able", ADJ), ("ible", ADJ), ("ive", ADJ), ("less", ADJ), ("ical", ADJ),
+];
+
+pub struct TagModel<'a> {
+  pub word_tags: HashMap<&'a str, usize>,
+  pub transitions: HashMap<[usize; 4], usize>,
+  pub contexts: HashMap<[usize; 3], usize>,
+}
+
+pub fn get_tag_index(tag: &str) -> Result<usize, String> {
+  match TAGS.iter().position(|t| t.eq_ignore_ascii_case(tag)) {
+    Some(index) => Ok(index),
+    None => Err(format!("Unknown part of speech '{}', must be one of: {}", tag, TAGS.join(", "))),
+  }
+}
+
+pub fn get_bundled_lexicon() -> HashMap<String, usize> {
+  let mut lexicon = HashMap::new();
+  for (tag, words) in LEXICON.iter() {
+    for word in words.split_whitespace() {
+      lexicon.entry(word.to_string()).or_insert(*tag);
+    }
+  }
+  // the first tag wins, ie: "that" is a determiner, not a conjunction.
+
+  lexicon
+}
+
+pub fn load_lexicon(file_name: &str) -> Result<HashMap<String, usize>, String> {
+  let contents = utils::get_file_contents_as_string(file_name)?;
+  let mut lexicon = get_bundled_lexicon();
+  for (line_num, line) in contents.lines().enumerate() {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+      continue;
+    }
+
+    let parts: Vec<&str> = line.split_whitespace().collect();
+    if parts.len() != 2 {
+      return Err(format!("Line {} of '{}' must be a word and a tag, ie: walk VERB", line_num + 1, file_name));
+    }
+    lexicon.insert(parts[0].to_lowercase(), get_tag_index(parts[1])?);
+  }
+  // the lexicon file is added on top of the bundled one, and
+  // its tags win for words that are in both.
+
+  Ok(lexicon)
+}
+
+pub fn guess_tag(word: &str, previous_tag: usize, lexicon: &HashMap<String, usize>) -> usize {
+  if let Some(tag) = lexicon.get(word) {
+    return *tag;
+  }
+  if utils::is_punctuation(word) {
+    return PUNCT;
+  }
+  if word.starts_with(|c: char| c.is_ascii_digit()) {
+    return NUM;
+  }
+  for (suffix, tag) in SUFFIXES.iter() {
+    if word.len() > suffix.len() + 2 && word.ends_with(suffix) {
+      return *tag;
+    }
+  }
+
+  match previous_tag {
+    PRON | AUX => VERB,
+    _ => NOUN,
+  }
+  // a word that is not in the lexicon and has no telling suffix is
+  // most likely a noun, unless it comes right after a subject or an
+  // auxiliary, ie: "they walk", "will walk".
+}
+
+pub fn is_function_word(word: &str) -> bool {
+  LEXICON.iter()
+    .filter(|(tag, _)| [DET, PRON, AUX, ADP, CONJ, ADV].contains(tag))
+    .any(|(_, words)| words.split_whitespace().any(|w| w == word))
+}
+
+pub fn is_verb(tag: usize) -> bool {
+  tag == VERB || tag == AUX
+}
+
+pub fn get_context(tags: &[usize]) -> [usize; 3] {
+  let has_verb = tags.iter().rev()
+    .take(CLAUSE_WINDOW)
+    .take_while(|tag| **tag != PUNCT)
+    .any(|tag| is_verb(*tag));
+  let previous = |back: usize| if tags.len() >= back { tags[tags.len() - back] } else { PUNCT };
+  // the start of the text counts as the end of a sentence. besides
+  // the last two tags, the context says whether the last few words since
+  // the last punctuation had a verb, so a subject with an adjective or
+  // adverb after it still tends to be followed by a verb.
+
+  [previous(2), previous(1), has_verb as usize]
+}
+
+pub fn tag_words<'a>(words: &[&'a str], lexicon: &HashMap<String, usize>) -> HashMap<&'a str, usize> {
+  let mut tag_counts: HashMap<&str, [usize; TAGS.len()]> = HashMap::new();
+  let mut previous_tag = PUNCT;
+  for word in words {
+    let tag = guess_tag(word, previous_tag, lexicon);
+    tag_counts.entry(word).or_insert([0; TAGS.len()])[tag] += 1;
+    previous_tag = tag;
+  }
+
+  let mut word_tags = HashMap::new();
+  for (word, counts) in tag_counts {
+    let mut best_tag = 0;
+    for tag in 0..TAGS.len() {
+      if counts[tag] > counts[best_tag] {
+        best_tag = tag;
+      }
+    }
+    word_tags.insert(word, best_tag);
+  }
+  // every word gets the tag it has most often, so a candidate word
+  // always has the same tag, no matter where it is used.
+
+  word_tags
+}
+
+pub fn generate_tag_model<'a>(text: &'a str, lexicon: &HashMap<String, usize>) -> TagModel<'a> {
+  let words: Vec<&str> = text.split_whitespace().collect();
+  let word_tags = tag_words(&words, lexicon);
+
+  let mut transitions = HashMap::new();
+  let mut contexts = HashMap::new();
+  let mut tags = vec![];
+  for word in &words {
+    let tag = word_tags[word];
+    let context = get_context(&tags);
+    *transitions.entry([context[0], context[1], context[2], tag]).or_insert(0) += 1;
+    *contexts.entry(context).or_insert(0) += 1;
+    tags.push(tag);
+  }
+  // the sentences of the mimic text are templates of tags, and the
+  // model learns which tag is likely to come next in them.
+
+  TagModel {
+    word_tags,
+    transitions,
+    contexts,
+  }
+}
+
+pub fn get_tag_model<'a>(matches: &ArgMatches, text: &'a str) -> Result<Option<TagModel<'a>>, String> {
+  let lexicon = match matches.value_of("pos_lexicon") {
+    Some(file_name) => load_lexicon(file_name)?,
+    None if matches.is_present("pos") => get_bundled_lexicon(),
+    None => return Ok(None),
+  };
+
+  let tag_model = generate_tag_model(text, &lexicon);
+  let mut tag_counts = [0; TAGS.len()];
+  for tag in tag_model.word_tags.values() {
+    tag_counts[*tag] += 1;
+  }
+  // print summary
+  let summary: Vec<String> = TAGS.iter().zip(tag_counts.iter()).map(|(tag, count)| format!("{} {}", count, tag)).collect();
+  println!("part of speech: tagged {} different words ({})", tag_model.word_tags.len(), summary.join(", "));
+
+  Ok(Some(tag_model))
+}
+
+pub fn get_tag_probability(tag_model: &TagModel, word: &str, current_words: &[&str]) -> f64 {
+  let tag = match tag_model.word_tags.get(word) {
+    Some(tag) => *tag,
+    None => return 1.0 / TAGS.len() as f64,
+  };
+
+  let start = current_words.len().saturating_sub(CLAUSE_WINDOW);
+  let tags: Vec<usize> = current_words[start..].iter()
+    .map(|w| tag_model.word_tags.get(w).cloned().unwrap_or(NOUN))
+    .collect();
+  let context = get_context(&tags);
+
+  let count = tag_model.transitions.get(&[context[0], context[1], context[2], tag]).cloned().unwrap_or(0);
+  let context_count = tag_model.contexts.get(&context).cloned().unwrap_or(0);
+  (count + 1) as f64 / (context_count + TAGS.len()) as f64
+  // add one smoothing, so a tag that never follows this context is
+  // unlikely but still possible when it is the only candidate.
+}
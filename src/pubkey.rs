@@ -0,0 +1,383 @@
+use std::fs;
+use std::io::Write;
+
+use clap::ArgMatches;
+use chacha20::ChaCha20;
+use chacha20::cipher::{KeyIvInit, StreamCipher};
+use rand::RngCore;
+use rand::rngs::OsRng;
+use sha2::{Sha256, Digest};
+use x25519_dalek::{PublicKey, StaticSecret};
+use zeroize::Zeroizing;
+
+use super::decode;
+use super::encode;
+use super::chat;
+use super::utils;
+
+const PRIVATE_KEY_LABEL: &str = "text-steg private key";
+const PUBLIC_KEY_LABEL: &str = "text-steg public key";
+const HEADER_SEED: &str = "text-steg public key header";
+const HEADER_SEPARATOR: &str = "\n\n";
+const KEY_LEN: usize = 32;
+
+pub fn to_hex(bytes: &[u8]) -> String {
+  bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+pub fn from_hex(text: &str) -> Result<Vec<u8>, String> {
+  if text.len() % 2 != 0 || !text.chars().all(|c| c.is_ascii_hexdigit()) {
+    return Err("Key is not valid hex".into());
+  }
+
+  (0..text.len()).step_by(2)
+    .map(|i| u8::from_str_radix(&text[i..i + 2], 16).map_err(|_| String::from("Key is not valid hex")))
+    .collect()
+}
+
+pub fn read_key_file(file_name: &str, label: &str) -> Result<[u8; KEY_LEN], String> {
+  let contents = Zeroizing::new(utils::get_file_contents_as_string(file_name)?);
+  let mut lines = contents.lines();
+  if lines.next().map(str::trim) != Some(label) {
+    return Err(format!("'{}' is not a {} file", file_name, label));
+  }
+
+  let bytes = Zeroizing::new(from_hex(lines.next().unwrap_or("").trim())?);
+  if bytes.len() != KEY_LEN {
+    return Err(format!("The key in '{}' must be {} bytes long", file_name, KEY_LEN));
+  }
+
+  let mut key = [0u8; KEY_LEN];
+  key.copy_from_slice(&bytes);
+  Ok(key)
+}
+
+pub fn write_key_file(file_name: &str, label: &str, key: &[u8], private: bool) -> Result<(), String> {
+  let contents = Zeroizing::new(format!("{}\n{}\n", label, to_hex(key)));
+  let mut options = fs::OpenOptions::new();
+  options.write(true).create(true).truncate(true);
+
+  #[cfg(unix)]
+  {
+    use std::os::unix::fs::OpenOptionsExt;
+    if private {
+      options.mode(0o600);
+    }
+  }
+  // only the owner should be able to read a private key
+
+  let mut file = options.open(file_name).map_err(|e| format!("Failed to write '{}': {}", file_name, e))?;
+  file.write_all(contents.as_bytes()).map_err(|e| format!("Failed to write '{}': {}", file_name, e))
+}
+
+pub fn generate_secret() -> StaticSecret {
+  let mut bytes = Zeroizing::new([0u8; KEY_LEN]);
+  OsRng.fill_bytes(&mut *bytes);
+  StaticSecret::from(*bytes)
+}
+
+pub fn derive_key(label: &str, shared_secret: &[u8], ephemeral_public: &PublicKey, recipient_public: &PublicKey) -> Zeroizing<[u8; KEY_LEN]> {
+  let mut hasher = Sha256::default();
+  hasher.input(label.as_bytes());
+  hasher.input(shared_secret);
+  hasher.input(ephemeral_public.as_bytes());
+  hasher.input(recipient_public.as_bytes());
+
+  let mut key = Zeroizing::new([0u8; KEY_LEN]);
+  key.copy_from_slice(&hasher.result());
+  key
+}
+
+pub fn get_seed_and_key(
+  shared_secret: &[u8],
+  ephemeral_public: &PublicKey,
+  recipient_public: &PublicKey,
+) -> (Zeroizing<String>, Zeroizing<[u8; KEY_LEN]>) {
+  let seed_bytes = derive_key("text-steg seed", shared_secret, ephemeral_public, recipient_public);
+  let key = derive_key("text-steg key", shared_secret, ephemeral_public, recipient_public);
+  // two different keys from the same exchange: one replaces the seed,
+  // the other encrypts the payload, so the words do not leak the key.
+
+  (Zeroizing::new(to_hex(&*seed_bytes)), key)
+}
+
+pub fn get_header_seed(recipient_public: &PublicKey) -> String {
+  // the ephemeral public key is not secret, but a fixed seed would let
+  // anyone decode the header of any text and see that it is a key. with
+  // the public key of the recipient in the seed, only someone who knows
+  // who the text is for can do that.
+  format!("{}:{}", HEADER_SEED, to_hex(recipient_public.as_bytes()))
+}
+
+pub fn apply_keystream(key: &[u8; KEY_LEN], data: &mut [u8]) {
+  let nonce = [0u8; 12];
+  let mut cipher = ChaCha20::new(key.into(), &nonce.into());
+  cipher.apply_keystream(data);
+  // every message has its own ephemeral key, so the key is never
+  // used twice and a fixed nonce is safe.
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn encode_to_recipient(
+  contents: &[u8],
+  kind: utils::PayloadKind,
+  model: &encode::Model,
+  recipient_file: &str,
+  value_mode: utils::ValueMode,
+  use_shuffle: bool,
+  n_depth: usize,
+  consecutive_skips: usize,
+  depth_skip_threshold: usize,
+  num_bits: usize,
+  length: Option<encode::LengthTarget>,
+  chat: Option<chat::Chat>,
+) -> Result<String, String> {
+  let recipient_public = PublicKey::from(read_key_file(recipient_file, PUBLIC_KEY_LABEL)?);
+  let ephemeral_secret = generate_secret();
+  let ephemeral_public = PublicKey::from(&ephemeral_secret);
+  let shared_secret = ephemeral_secret.diffie_hellman(&recipient_public);
+  let (seed_str, key) = get_seed_and_key(shared_secret.as_bytes(), &ephemeral_public, &recipient_public);
+
+  let mut payload = utils::frame_payload(contents, &seed_str, kind);
+  apply_keystream(&key, &mut payload);
+
+  let mut header_bytes = *ephemeral_public.as_bytes();
+  header_bytes[31] |= OsRng.next_u32() as u8 & 0x80;
+  // x25519 never uses the top bit, so it is always 0 in a public key.
+  // it is set randomly, like it would be in random bytes.
+
+  let header_seed = get_header_seed(&recipient_public);
+  let header = encode::encode_to_string(
+    &header_bytes,
+    &header_seed,
+    model,
+    value_mode,
+    use_shuffle,
+    n_depth,
+    consecutive_skips,
+    depth_skip_threshold,
+    num_bits,
+    None,
+    chat,
+  )?;
+  // the recipient can make the header seed from their own key.
+  // the header bytes are a curve point, which random bytes are only
+  // half of the time, so someone with the public key can tell over
+  // several texts that they are encoded to it. see the readme.
+
+  let body = encode::encode_to_string(
+    &payload,
+    &seed_str,
+    model,
+    value_mode,
+    use_shuffle,
+    n_depth,
+    consecutive_skips,
+    depth_skip_threshold,
+    num_bits,
+    length,
+    chat,
+  )?;
+  // the header always has the same size, so only the body
+  // needs to be brought to the length.
+
+  // the decoder does not look for the separator, --format and
+  // the channel the text is sent over can change the paragraphs.
+  Ok([header, body].join(HEADER_SEPARATOR))
+}
+
+pub fn decode_with_private_key(
+  contents: &str,
+  key_file: &str,
+  alg: utils::Algorithm,
+  num_bits: usize,
+  n_depth: usize,
+  adaptive_model: Option<&decode::AdaptiveModel>,
+) -> Result<(utils::PayloadKind, Vec<u8>), String> {
+  let recipient_secret = StaticSecret::from(read_key_file(key_file, PRIVATE_KEY_LABEL)?);
+  let recipient_public = PublicKey::from(&recipient_secret);
+
+  let header_seed = get_header_seed(&recipient_public);
+  let header_bytes = decode::decode_to_bytes(contents, &header_seed, alg, num_bits, n_depth, adaptive_model, None)?;
+  if header_bytes.len() < KEY_LEN {
+    return Err("The public key header is too short. Was this text encoded with --recipient?".into());
+  }
+  let mut ephemeral_bytes = [0u8; KEY_LEN];
+  ephemeral_bytes.copy_from_slice(&header_bytes[..KEY_LEN]);
+  ephemeral_bytes[31] &= 0x7f;
+  // the header is at the start, so its bytes are the first ones no
+  // matter what the words of the body decode to with the header seed.
+  let ephemeral_public = PublicKey::from(ephemeral_bytes);
+
+  let shared_secret = recipient_secret.diffie_hellman(&ephemeral_public);
+  let (seed_str, key) = get_seed_and_key(shared_secret.as_bytes(), &ephemeral_public, &recipient_public);
+
+  let is_header = |header: &[u8]| {
+    let mut header = header[..header.len().min(utils::FRAME_HEADER_LEN)].to_vec();
+    apply_keystream(&key, &mut header);
+    utils::get_framed_length(&header, &seed_str).is_ok()
+  };
+  let mut payload = match decode::find_framed_region(contents, &seed_str, alg, num_bits, n_depth, adaptive_model, None, &is_header)? {
+    Some(payload) => payload,
+    None => return Err("Could not find the payload. Was it encoded to the public key of this private key?".into()),
+  };
+  apply_keystream(&key, &mut payload);
+  // the body starts at the first word where the payload header
+  // shows up after decrypting. the header always ends before it.
+
+  match utils::unframe_payload(&payload, &seed_str) {
+    Ok((utils::PayloadKind::Share, _)) => Err("The payload is a share, but --recipient can not be combined with --split".into()),
+    Ok((kind, payload)) => Ok((kind, payload)),
+    Err(_) => Err("Could not decrypt the payload. Was it encoded to the public key of this private key?".into()),
+  }
+}
+
+pub fn keygen(matches: &ArgMatches) -> Result<(), String> {
+  let output = utils::get_value(matches, "output")?;
+  let public_output = match matches.value_of("public") {
+    Some(name) => name.to_string(),
+    None => format!("{}.pub", output),
+  };
+
+  let secret = generate_secret();
+  let public = PublicKey::from(&secret);
+  write_key_file(output, PRIVATE_KEY_LABEL, &secret.to_bytes(), true)?;
+  write_key_file(&public_output, PUBLIC_KEY_LABEL, public.as_bytes(), false)?;
+
+  println!("\nsaved private key to {}", output);
+  println!("saved public key to {}", public_output);
+  println!("public key: {}\n", to_hex(public.as_bytes()));
+  println!("Publish the public key so anyone can encode to you with --recipient {}", public_output);
+  println!("Keep the private key secret, you need it to decode with --key {}\n", output);
+
+  Ok(())
+}
+
+pub fn pubkey(matches: &ArgMatches) -> Result<(), String> {
+  let key_file = utils::get_value(matches, "key")?;
+  let secret = StaticSecret::from(read_key_file(key_file, PRIVATE_KEY_LABEL)?);
+  let public = PublicKey::from(&secret);
+
+  if let Some(output) = matches.value_of("output") {
+    write_key_file(output, PUBLIC_KEY_LABEL, public.as_bytes(), false)?;
+    println!("saved public key to {}", output);
+  } else {
+    print!("{}\n{}\n", PUBLIC_KEY_LABEL, to_hex(public.as_bytes()));
+  }
+
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use super::super::corpus;
+
+  fn get_test_corpus() -> corpus::Corpus {
+    let mut state: u32 = 12345;
+    let mut next = || {
+      state ^= state << 13;
+      state ^= state >> 17;
+      state ^= state << 5;
+      state
+    };
+    let vocab: Vec<String> = (0..300)
+      .map(|_| (0..3 + next() % 6).map(|_| (b'a' + (next() % 26) as u8) as char).collect())
+      .collect();
+    let words: Vec<&str> = (0..5000).map(|_| vocab[next() as usize % vocab.len()].as_str()).collect();
+    let text = words.join(" ");
+    let end = text.len();
+
+    corpus::Corpus {
+      text,
+      parts: vec![corpus::CorpusPart { source: "test".into(), start: 0, end, weight: 1.0 }],
+    }
+  }
+
+  fn get_test_dir(name: &str) -> std::path::PathBuf {
+    let dir = std::env::temp_dir().join(format!("text-steg-test-{}-{}", name, std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    dir
+  }
+
+  #[test]
+  fn from_hex_works() {
+    assert_eq!(from_hex("00ff7a").unwrap(), vec![0x00, 0xff, 0x7a]);
+    assert_eq!(from_hex("").unwrap(), Vec::<u8>::new());
+    assert!(from_hex("abc").is_err());
+    assert!(from_hex("zz").is_err());
+    assert!(from_hex("0x12").is_err());
+  }
+
+  #[test]
+  fn read_key_file_rejects_bad_keys() {
+    let dir = get_test_dir("bad-keys");
+    let bad_hex = dir.join("bad_hex.pub");
+    fs::write(&bad_hex, format!("{}\nnot hex\n", PUBLIC_KEY_LABEL)).unwrap();
+    assert!(read_key_file(bad_hex.to_str().unwrap(), PUBLIC_KEY_LABEL).is_err());
+
+    let short_key = dir.join("short.pub");
+    fs::write(&short_key, format!("{}\n00ff\n", PUBLIC_KEY_LABEL)).unwrap();
+    assert!(read_key_file(short_key.to_str().unwrap(), PUBLIC_KEY_LABEL).is_err());
+
+    let wrong_label = dir.join("wrong_label.pub");
+    fs::write(&wrong_label, format!("{}\n{}\n", PRIVATE_KEY_LABEL, to_hex(&[0; KEY_LEN]))).unwrap();
+    assert!(read_key_file(wrong_label.to_str().unwrap(), PUBLIC_KEY_LABEL).is_err());
+
+    fs::remove_dir_all(dir).unwrap();
+  }
+
+  #[test]
+  fn encode_to_recipient_round_trips() {
+    let dir = get_test_dir("round-trip");
+    let private_file = dir.join("key");
+    let public_file = dir.join("key.pub");
+    let secret = generate_secret();
+    write_key_file(private_file.to_str().unwrap(), PRIVATE_KEY_LABEL, &secret.to_bytes(), true).unwrap();
+    write_key_file(public_file.to_str().unwrap(), PUBLIC_KEY_LABEL, PublicKey::from(&secret).as_bytes(), false).unwrap();
+
+    let word_file_data = get_test_corpus();
+    let model = encode::generate_model(&word_file_data, 2);
+    let value_mode = utils::ValueMode::CharValueMap(2);
+    let text = encode_to_recipient(
+      b"secret message",
+      utils::PayloadKind::Data,
+      &model,
+      public_file.to_str().unwrap(),
+      value_mode,
+      true,
+      2,
+      0,
+      0,
+      2,
+      None,
+      None,
+    ).unwrap();
+
+    let alg = utils::Algorithm::Shuffle(value_mode);
+    let (kind, payload) = decode_with_private_key(&text, private_file.to_str().unwrap(), alg, 2, 2, None).unwrap();
+    assert!(kind == utils::PayloadKind::Data);
+    assert_eq!(payload, b"secret message");
+
+    let other_file = dir.join("other");
+    write_key_file(other_file.to_str().unwrap(), PRIVATE_KEY_LABEL, &generate_secret().to_bytes(), true).unwrap();
+    assert!(decode_with_private_key(&text, other_file.to_str().unwrap(), alg, 2, 2, None).is_err());
+
+    fs::remove_dir_all(dir).unwrap();
+  }
+
+  #[test]
+  fn short_header_is_an_error() {
+    let dir = get_test_dir("short-header");
+    let private_file = dir.join("key");
+    write_key_file(private_file.to_str().unwrap(), PRIVATE_KEY_LABEL, &generate_secret().to_bytes(), true).unwrap();
+
+    let alg = utils::Algorithm::Shuffle(utils::ValueMode::CharValueMap(2));
+    match decode_with_private_key("only a few words", private_file.to_str().unwrap(), alg, 2, 2, None) {
+      Err(e) => assert!(e.contains("too short"), "{}", e),
+      Ok(_) => panic!("a text with only a few words can not hold a public key header"),
+    }
+
+    fs::remove_dir_all(dir).unwrap();
+  }
+}
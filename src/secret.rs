@@ -0,0 +1,156 @@
+use std::env;
+use std::fs::File;
+use std::io::Read;
+
+use clap::ArgMatches;
+use zeroize::{Zeroize, Zeroizing};
+
+pub fn read_secret_from(reader: &mut dyn Read, source_name: &str) -> Result<Zeroizing<String>, String> {
+  let mut bytes = Zeroizing::new(vec![]);
+  reader.read_to_end(&mut bytes).map_err(|e| format!("Failed to read secret from {}: {}", source_name, e))?;
+
+  let mut secret = match String::from_utf8(bytes.to_vec()) {
+    Ok(secret) => Zeroizing::new(secret),
+    Err(e) => {
+      e.into_bytes().zeroize();
+      return Err(format!("Secret from {} is not valid UTF-8", source_name));
+    },
+  };
+
+  if secret.ends_with('\n') {
+    secret.pop();
+    if secret.ends_with('\r') {
+      secret.pop();
+    }
+  }
+  // only one line ending is removed, so that `echo secret > file`
+  // works but a secret can still end with whitespace.
+
+  if secret.is_empty() {
+    return Err(format!("Secret from {} is empty", source_name));
+  }
+
+  Ok(secret)
+}
+
+#[cfg(unix)]
+pub fn read_secret_from_fd(fd: i32) -> Result<Zeroizing<String>, String> {
+  let mut file = File::open(format!("/dev/fd/{}", fd)).map_err(|_| format!("File descriptor {} is not open", fd))?;
+  // the file descriptor is handed to us by whoever started the process,
+  // ie: `3< keyfile` or a pipe. opening it through /dev/fd gives an
+  // error instead of undefined behavior if it is not open.
+  read_secret_from(&mut file, &format!("file descriptor {}", fd))
+}
+
+#[cfg(not(unix))]
+pub fn read_secret_from_fd(_fd: i32) -> Result<Zeroizing<String>, String> {
+  Err("--seed-fd is only supported on unix systems".into())
+}
+
+pub fn get_num_secret_sources(matches: &ArgMatches) -> usize {
+  let mut num_sources = 0;
+  if matches.occurrences_of("seed") > 0 {
+    num_sources += 1;
+  }
+  if matches.value_of("password") == Some("true") {
+    num_sources += 1;
+  }
+  for source in ["seed_file", "seed_env", "seed_fd"].iter() {
+    if matches.is_present(source) {
+      num_sources += 1;
+    }
+  }
+
+  num_sources
+}
+
+pub fn is_default_seed(matches: &ArgMatches) -> bool {
+  get_num_secret_sources(matches) == 0
+}
+
+pub fn get_secret_from_sources(
+  matches: &ArgMatches,
+  file_arg: &str,
+  env_arg: &str,
+  fd_arg: &str,
+) -> Result<Option<Zeroizing<String>>, String> {
+  if let Some(file_name) = matches.value_of(file_arg) {
+    let mut file = File::open(file_name).map_err(|_| format!("Failed to read file: '{}'", file_name))?;
+    return Ok(Some(read_secret_from(&mut file, &format!("'{}'", file_name))?));
+  }
+
+  if let Some(var_name) = matches.value_of(env_arg) {
+    let secret = match env::var(var_name) {
+      Ok(secret) => Zeroizing::new(secret),
+      Err(_) => return Err(format!("Environment variable '{}' is not set or not valid UTF-8", var_name)),
+    };
+    if secret.is_empty() {
+      return Err(format!("Environment variable '{}' is empty", var_name));
+    }
+    return Ok(Some(secret));
+  }
+
+  if let Some(fd) = matches.value_of(fd_arg) {
+    let fd = fd.parse::<i32>().map_err(|_| format!("Invalid file descriptor: {}", fd))?;
+    return Ok(Some(read_secret_from_fd(fd)?));
+  }
+
+  Ok(None)
+  // the same sources are used for the seed and the decoy seed,
+  // only the names of the arguments are different.
+}
+
+pub fn get_secret(matches: &ArgMatches, prompt: &str, confirm: bool) -> Result<Zeroizing<String>, String> {
+  if get_num_secret_sources(matches) > 1 {
+    return Err("Only one of --seed, --pass, --seed-file, --seed-env and --seed-fd can be used".into());
+  }
+
+  if let Some(secret) = get_secret_from_sources(matches, "seed_file", "seed_env", "seed_fd")? {
+    return Ok(secret);
+  }
+
+  if matches.value_of("password") == Some("true") {
+    // get seed string interactively
+    let pass = Zeroizing::new(rpassword::prompt_password_stderr(prompt).unwrap());
+    if confirm {
+      let confirmation = Zeroizing::new(rpassword::prompt_password_stderr("Enter password again: ").unwrap());
+      if *pass != *confirmation {
+        return Err("Passwords do not match".into());
+      }
+    }
+    return Ok(pass);
+  }
+
+  match matches.value_of("seed") {
+    Some(seed) => Ok(Zeroizing::new(seed.to_string())),
+    None => Err("Must provide argument for seed".into()),
+  }
+}
+
+pub fn get_decoy_secret(matches: &ArgMatches) -> Result<Zeroizing<String>, String> {
+  let num_sources = ["decoy_seed", "decoy_seed_file", "decoy_seed_env", "decoy_seed_fd"].iter()
+    .filter(|source| matches.is_present(source))
+    .count();
+  if num_sources > 1 {
+    return Err("Only one of --decoy-seed, --decoy-seed-file, --decoy-seed-env and --decoy-seed-fd can be used".into());
+  }
+
+  if let Some(secret) = get_secret_from_sources(matches, "decoy_seed_file", "decoy_seed_env", "decoy_seed_fd")? {
+    return Ok(secret);
+  }
+
+  if let Some(seed) = matches.value_of("decoy_seed") {
+    return Ok(Zeroizing::new(seed.to_string()));
+  }
+
+  let pass = Zeroizing::new(rpassword::prompt_password_stderr("Enter decoy password: ").unwrap());
+  let confirmation = Zeroizing::new(rpassword::prompt_password_stderr("Enter decoy password again: ").unwrap());
+  if *pass != *confirmation {
+    return Err("Passwords do not match".into());
+  }
+  if pass.is_empty() {
+    return Err("The decoy password can not be empty".into());
+  }
+
+  Ok(pass)
+}
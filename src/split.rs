@@ -0,0 +1,371 @@
+use std::fs;
+use std::path::Path;
+
+use rand::RngCore;
+use rand::rngs::OsRng;
+
+use super::chat;
+use super::encode;
+use super::format;
+use super::template;
+use super::utils;
+
+const SHARE_HEADER_LEN: usize = 7;
+const MAX_SHARES: usize = 255;
+
+pub struct Share {
+  pub threshold: usize,
+  pub x: u8,
+  pub num_shares: usize,
+  pub set_id: [u8; 4],
+  pub y_values: Vec<u8>,
+}
+
+pub fn parse_split(split_str: &str) -> Result<(usize, usize), String> {
+  let error = || format!("--split must look like k-of-n, ie: 2-of-3, not '{}'", split_str);
+  let mut parts = split_str.splitn(2, "-of-");
+  let threshold: usize = parts.next().and_then(|k| k.parse().ok()).ok_or_else(error)?;
+  let num_shares: usize = parts.next().and_then(|n| n.parse().ok()).ok_or_else(error)?;
+
+  if threshold < 2 || threshold > num_shares || num_shares > MAX_SHARES {
+    return Err(format!(
+      "--split k-of-n needs 2 <= k <= n <= {}, you provided {}",
+      MAX_SHARES,
+      split_str,
+    ));
+  }
+
+  Ok((threshold, num_shares))
+}
+
+pub fn gf_mul(mut a: u8, mut b: u8) -> u8 {
+  let mut product = 0;
+  while b > 0 {
+    if b & 1 == 1 {
+      product ^= a;
+    }
+    let carry = a & 0x80;
+    a <<= 1;
+    if carry != 0 {
+      a ^= 0x1b;
+    }
+    b >>= 1;
+  }
+  // multiplication in GF(2^8) with the same polynomial as AES
+  // (x^8 + x^4 + x^3 + x + 1). addition and subtraction are both xor.
+
+  product
+}
+
+pub fn gf_inverse(a: u8) -> u8 {
+  let mut result = 1;
+  for _ in 0..254 {
+    result = gf_mul(result, a);
+  }
+  // a^255 = 1 for every a != 0, so a^254 is the inverse of a
+
+  result
+}
+
+pub fn split_secret(secret: &[u8], threshold: usize, num_shares: usize) -> Vec<Share> {
+  let mut set_id = [0u8; 4];
+  OsRng.fill_bytes(&mut set_id);
+
+  let mut shares: Vec<Share> = (1..=num_shares).map(|x| Share {
+    threshold,
+    x: x as u8,
+    num_shares,
+    set_id,
+    y_values: Vec::with_capacity(secret.len()),
+  }).collect();
+
+  let mut coefficients = vec![0u8; threshold];
+  for byte in secret {
+    coefficients[0] = *byte;
+    OsRng.fill_bytes(&mut coefficients[1..]);
+    for share in shares.iter_mut() {
+      let mut y = 0;
+      for coefficient in coefficients.iter().rev() {
+        y = gf_mul(y, share.x) ^ coefficient;
+      }
+      share.y_values.push(y);
+    }
+  }
+  // every byte of the secret is the constant term of its own random
+  // polynomial of degree k - 1. each share is that polynomial evaluated
+  // at x = the share number, and any k points give back the polynomial.
+
+  shares
+}
+
+pub fn combine_shares(shares: &[Share]) -> Result<Vec<u8>, String> {
+  let first = match shares.first() {
+    Some(first) => first,
+    None => return Err("No shares to combine".into()),
+  };
+
+  let mut used_shares: Vec<&Share> = vec![];
+  for share in shares {
+    if share.set_id != first.set_id ||
+      share.threshold != first.threshold ||
+      share.num_shares != first.num_shares ||
+      share.y_values.len() != first.y_values.len()
+    {
+      return Err("The files are shares of different payloads".into());
+    }
+    match used_shares.iter().find(|used| used.x == share.x) {
+      Some(used) if used.y_values != share.y_values => {
+        return Err(format!("Two different files claim to be share {}", share.x));
+      },
+      Some(_) => {},
+      None => used_shares.push(share),
+    }
+  }
+  // the same file given twice only counts once, but two shares with
+  // the same number and different values can not both be right.
+
+  if used_shares.len() < first.threshold {
+    return Err(format!(
+      "Need {} different shares to rebuild the payload, but only found {}",
+      first.threshold,
+      used_shares.len(),
+    ));
+  }
+  used_shares.truncate(first.threshold);
+
+  let mut secret = vec![0u8; first.y_values.len()];
+  for (i, share) in used_shares.iter().enumerate() {
+    let mut basis = 1;
+    for (j, other) in used_shares.iter().enumerate() {
+      if i != j {
+        basis = gf_mul(basis, gf_mul(other.x, gf_inverse(other.x ^ share.x)));
+      }
+    }
+    for (secret_byte, y) in secret.iter_mut().zip(share.y_values.iter()) {
+      *secret_byte ^= gf_mul(basis, *y);
+    }
+  }
+  // lagrange interpolation at x = 0 gives the constant term of
+  // every polynomial, ie: the bytes of the secret.
+
+  Ok(secret)
+}
+
+pub fn combine_payload(shares: &[Share]) -> Result<(utils::PayloadKind, Vec<u8>), String> {
+  let secret = combine_shares(shares)?;
+  match secret.split_first() {
+    Some((kind, payload)) => Ok((utils::get_payload_kind(*kind)?, payload.to_vec())),
+    None => Err("The shares do not hold a payload".into()),
+  }
+}
+
+pub fn share_to_bytes(share: &Share, seed_str: &str) -> Vec<u8> {
+  let mut bytes = vec![share.threshold as u8, share.x, share.num_shares as u8];
+  bytes.extend_from_slice(&share.set_id);
+  bytes.extend_from_slice(&share.y_values);
+
+  utils::frame_payload(&bytes, seed_str, utils::PayloadKind::Share)
+  // the header says that this is a share, so the decoder knows
+  // that it needs the other shares as well.
+}
+
+pub fn share_from_bytes(bytes: &[u8]) -> Result<Share, String> {
+  if bytes.len() < SHARE_HEADER_LEN {
+    return Err("Not a valid share".into());
+  }
+  let (threshold, x, num_shares) = (bytes[0], bytes[1], bytes[2]);
+  if threshold < 2 || x == 0 || threshold > num_shares || x > num_shares {
+    return Err("Not a valid share".into());
+  }
+
+  let mut set_id = [0u8; 4];
+  set_id.copy_from_slice(&bytes[3..SHARE_HEADER_LEN]);
+  Ok(Share {
+    threshold: threshold as usize,
+    x,
+    num_shares: num_shares as usize,
+    set_id,
+    y_values: bytes[SHARE_HEADER_LEN..].to_vec(),
+  })
+}
+
+pub fn get_share_file_name(output: &str, x: u8) -> String {
+  let path = Path::new(output);
+  match (path.file_stem(), path.extension()) {
+    (Some(stem), Some(extension)) => {
+      path.with_file_name(format!("{}.{}.{}", stem.to_string_lossy(), x, extension.to_string_lossy()))
+        .to_string_lossy()
+        .into_owned()
+    },
+    _ => format!("{}.{}", output, x),
+  }
+  // out.txt becomes out.1.txt, out.2.txt, ...
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn encode_split(
+  contents: &[u8],
+  kind: utils::PayloadKind,
+  seed_str: &str,
+  model: &encode::Model,
+  output: &str,
+  threshold: usize,
+  num_shares: usize,
+  value_mode: utils::ValueMode,
+  use_shuffle: bool,
+  n_depth: usize,
+  consecutive_skips: usize,
+  depth_skip_threshold: usize,
+  num_bits: usize,
+  length: Option<encode::LengthTarget>,
+  template: Option<&template::Template>,
+  output_format: &format::FormatOptions,
+  chat: Option<chat::Chat>,
+) -> Result<(), String> {
+  let shares = split_secret(&[&[kind as u8], contents].concat(), threshold, num_shares);
+  // the kind of the payload is split along with it, so it is
+  // only known once enough shares are put back together.
+  let mut file_names = vec![];
+  for share in &shares {
+    let text_data = encode::encode_to_string(
+      &share_to_bytes(share, seed_str),
+      seed_str,
+      model,
+      value_mode,
+      use_shuffle,
+      n_depth,
+      consecutive_skips,
+      depth_skip_threshold,
+      num_bits,
+      length,
+      chat,
+    )?;
+    let text_data = match template {
+      Some(template) => template::fill_template(template, &text_data),
+      None => text_data,
+    };
+    let file_name = get_share_file_name(output, share.x);
+    fs::write(&file_name, format::format_text(&text_data, output_format)).unwrap();
+    file_names.push(file_name);
+  }
+
+  // print summary
+  println!("\nsplit {} bytes into {} stegotexts, any {} of them can rebuild it:", contents.len(), num_shares, threshold);
+  for file_name in file_names {
+    println!("  {}", file_name);
+  }
+  println!();
+
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn get_share_copy(share: &Share) -> Share {
+    Share {
+      threshold: share.threshold,
+      x: share.x,
+      num_shares: share.num_shares,
+      set_id: share.set_id,
+      y_values: share.y_values.clone(),
+    }
+  }
+
+  fn get_subsets(n: usize, k: usize) -> Vec<Vec<usize>> {
+    if k == 0 {
+      return vec![vec![]];
+    }
+    if n < k {
+      return vec![];
+    }
+    let mut subsets = get_subsets(n - 1, k);
+    for mut subset in get_subsets(n - 1, k - 1) {
+      subset.push(n - 1);
+      subsets.push(subset);
+    }
+    subsets
+  }
+
+  #[test]
+  fn gf_mul_known_products() {
+    assert_eq!(gf_mul(0x57, 0x83), 0xc1);
+    assert_eq!(gf_mul(0x57, 0x13), 0xfe);
+    assert_eq!(gf_mul(0x57, 0x02), 0xae);
+    assert_eq!(gf_mul(0x80, 0x02), 0x1b);
+    for a in 0..=255 {
+      assert_eq!(gf_mul(a, 0), 0);
+      assert_eq!(gf_mul(a, 1), a);
+      assert_eq!(gf_mul(a, 0x35), gf_mul(0x35, a));
+    }
+  }
+
+  #[test]
+  fn gf_inverse_is_the_inverse() {
+    assert_eq!(gf_inverse(0x53), 0xca);
+    for a in 1..=255 {
+      assert_eq!(gf_mul(a, gf_inverse(a)), 1);
+    }
+  }
+
+  #[test]
+  fn any_k_shares_rebuild_the_secret() {
+    let secret = b"any k of n shares give this back";
+    let shares = split_secret(secret, 3, 5);
+    for k in 3..=5 {
+      for subset in get_subsets(5, k) {
+        let chosen: Vec<Share> = subset.iter().map(|i| get_share_copy(&shares[*i])).collect();
+        assert_eq!(combine_shares(&chosen).unwrap(), secret.to_vec(), "shares {:?}", subset);
+      }
+    }
+  }
+
+  #[test]
+  fn fewer_than_k_shares_fail() {
+    let shares = split_secret(b"secret", 3, 5);
+    for subset in get_subsets(5, 2) {
+      let chosen: Vec<Share> = subset.iter().map(|i| get_share_copy(&shares[*i])).collect();
+      assert!(combine_shares(&chosen).is_err());
+    }
+
+    let repeated = vec![get_share_copy(&shares[0]), get_share_copy(&shares[0]), get_share_copy(&shares[1])];
+    assert!(combine_shares(&repeated).is_err());
+    assert!(combine_shares(&[]).is_err());
+  }
+
+  #[test]
+  fn mismatched_shares_fail() {
+    let shares = split_secret(b"secret", 2, 3);
+    let other_shares = split_secret(b"secret", 2, 3);
+    let mixed = vec![get_share_copy(&shares[0]), get_share_copy(&other_shares[1])];
+    assert!(combine_shares(&mixed).is_err());
+
+    let mut forged = get_share_copy(&shares[1]);
+    forged.x = shares[0].x;
+    let same_index = vec![get_share_copy(&shares[0]), forged, get_share_copy(&shares[2])];
+    assert!(combine_shares(&same_index).is_err());
+
+    let mut truncated = get_share_copy(&shares[1]);
+    truncated.y_values.pop();
+    assert!(combine_shares(&[get_share_copy(&shares[0]), truncated]).is_err());
+  }
+
+  #[test]
+  fn share_bytes_round_trip() {
+    let shares = split_secret(b"secret", 2, 3);
+    let bytes = share_to_bytes(&shares[1], "seed");
+    let (kind, data) = utils::unframe_payload(&bytes, "seed").unwrap();
+    assert!(kind == utils::PayloadKind::Share);
+    let share = share_from_bytes(&data).unwrap();
+    assert_eq!(share.x, shares[1].x);
+    assert_eq!(share.y_values, shares[1].y_values);
+
+    assert!(share_from_bytes(&data[..SHARE_HEADER_LEN - 1]).is_err());
+    let mut bad_index = data.clone();
+    bad_index[1] = 4;
+    assert!(share_from_bytes(&bad_index).is_err());
+    bad_index[1] = 0;
+    assert!(share_from_bytes(&bad_index).is_err());
+  }
+}
@@ -0,0 +1,204 @@
+use std::fs;
+use std::io::Cursor;
+use std::collections::HashMap;
+
+use rand::prelude::StdRng;
+use bitstream_io::{BigEndian, BitReader, BitWriter};
+
+use super::utils;
+
+pub struct SynonymDictionary {
+  pub synonym_sets: Vec<Vec<String>>,
+  pub word_to_set: HashMap<String, usize>,
+}
+
+pub fn load_synonym_sets(file_name: &str) -> Result<SynonymDictionary, String> {
+  let contents = utils::get_file_contents_as_string(file_name)?;
+  let mut synonym_sets = vec![];
+  let mut word_to_set = HashMap::new();
+
+  for line in contents.lines() {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+      continue;
+    }
+
+    let mut synonym_set: Vec<String> = vec![];
+    for word in line.split(',') {
+      let word = word.trim().to_lowercase();
+      if word.is_empty() || synonym_set.contains(&word) {
+        continue;
+      }
+      if !word.chars().all(char::is_alphabetic) {
+        return Err(format!("Synonyms must be single words, but found '{}'", word));
+      }
+      synonym_set.push(word);
+    }
+
+    if synonym_set.len() < 2 {
+      continue;
+    }
+
+    for word in &synonym_set {
+      if word_to_set.contains_key(word) {
+        return Err(format!("'{}' appears in more than one synonym set", word));
+      }
+      word_to_set.insert(word.clone(), synonym_sets.len());
+    }
+    // a word can only belong to one set, otherwise the decoder
+    // would not know which set a swapped in word came from.
+    synonym_sets.push(synonym_set);
+  }
+
+  Ok(SynonymDictionary { synonym_sets, word_to_set })
+}
+
+pub fn match_case(word: &str, original: &str) -> String {
+  let mut original_chars = original.chars();
+  let first_upper = original_chars.next().is_some_and(char::is_uppercase);
+  let rest_upper = original.chars().count() > 1 && original_chars.all(char::is_uppercase);
+
+  if first_upper && rest_upper {
+    word.to_uppercase()
+  } else if first_upper {
+    let mut chars = word.chars();
+    match chars.next() {
+      Some(c) => c.to_uppercase().chain(chars).collect(),
+      None => String::new(),
+    }
+  } else {
+    word.to_string()
+  }
+}
+
+pub fn get_keyed_synonym_order<'a>(rng: &mut StdRng, synonym_set: &'a [String]) -> Vec<&'a String> {
+  let mut ordered: Vec<&String> = synonym_set.iter().collect();
+  utils::shuffle_in_place(rng, &mut ordered);
+  // every eligible position gets its own random ordering of the set,
+  // so the same word does not always represent the same value.
+
+  ordered
+}
+
+pub fn encode_synonym(
+  contents: &[u8],
+  output: &str,
+  seed_str: &str,
+  cover_file_name: &str,
+  synonym_file_name: &str,
+  kind: utils::PayloadKind,
+) -> Result<(), String> {
+  let mut rng = utils::create_rng_from_seed(seed_str);
+  let mut position_rng = utils::create_position_rng(seed_str);
+  let cover_text = utils::get_file_contents_as_string(cover_file_name)?;
+  let dictionary = load_synonym_sets(synonym_file_name)?;
+
+  let framed_contents = utils::frame_payload(contents, seed_str, kind);
+  let mut cursor = Cursor::new(&framed_contents);
+  let mut num_bits_remain = framed_contents.len() * 8;
+  let mut bitreader = BitReader::endian(&mut cursor, BigEndian);
+
+  let mut text_data = String::from("");
+  let mut last_end = 0;
+  let mut positions_used = 0;
+  let mut swap_count = 0;
+
+  for (start, end) in utils::get_word_spans(&cover_text) {
+    if num_bits_remain == 0 {
+      break;
+    }
+
+    let original = &cover_text[start..end];
+    let set_index = match dictionary.word_to_set.get(&original.to_lowercase()) {
+      Some(index) => *index,
+      None => continue,
+    };
+    if !utils::is_data_position(&mut position_rng) {
+      continue;
+    }
+    // only a keyed subset of the dictionary words carry data, so
+    // the words that were swapped do not line up with every word
+    // in the cover text that has a synonym.
+
+    let synonym_set = &dictionary.synonym_sets[set_index];
+    let num_bits = utils::get_bits_for_candidates(synonym_set.len(), 8);
+    let ordered = get_keyed_synonym_order(&mut rng, synonym_set);
+
+    let num_bits_to_read = if num_bits_remain < num_bits {
+      num_bits_remain
+    } else {
+      num_bits
+    };
+    let mut value: u8 = bitreader.read(num_bits_to_read as u32).unwrap();
+    value <<= num_bits - num_bits_to_read;
+
+    let chosen = ordered[value as usize];
+    text_data.push_str(&cover_text[last_end..start]);
+    if chosen.as_str() == original.to_lowercase() {
+      text_data.push_str(original);
+    } else {
+      text_data.push_str(&match_case(chosen, original));
+      swap_count += 1;
+    }
+    // only swap if the value calls for a different member of the set,
+    // that way the output stays as close to the original as possible.
+
+    last_end = end;
+    positions_used += 1;
+    num_bits_remain -= num_bits_to_read;
+  }
+
+  if num_bits_remain > 0 {
+    return Err(format!(
+      "Cover text is too small: it ran out of synonyms with {} of {} bits left to encode",
+      num_bits_remain,
+      framed_contents.len() * 8,
+    ));
+  }
+
+  text_data.push_str(&cover_text[last_end..]);
+
+  // print summary
+  println!("\nencoded {} bytes into {} synonym positions", contents.len(), positions_used);
+  println!("swapped {} words of the cover text", swap_count);
+  println!("average bits per position: {}\n", ((framed_contents.len() * 8) as f64 / positions_used as f64));
+
+  fs::write(output, text_data).map_err(|e| format!("Failed to write '{}': {}", output, e))?;
+
+  Ok(())
+}
+
+pub fn get_synonym_payload(
+  contents: &str,
+  seed_str: &str,
+  dictionary: &SynonymDictionary,
+) -> Result<Vec<u8>, String> {
+  let mut rng = utils::create_rng_from_seed(seed_str);
+  let mut position_rng = utils::create_position_rng(seed_str);
+
+  let mut bitwriter = BitWriter::endian(Vec::new(), BigEndian);
+
+  for (start, end) in utils::get_word_spans(contents) {
+    let word = contents[start..end].to_lowercase();
+    let set_index = match dictionary.word_to_set.get(&word) {
+      Some(index) => *index,
+      None => continue,
+    };
+    if !utils::is_data_position(&mut position_rng) {
+      continue;
+    }
+
+    let synonym_set = &dictionary.synonym_sets[set_index];
+    let num_bits = utils::get_bits_for_candidates(synonym_set.len(), 8);
+    let ordered = get_keyed_synonym_order(&mut rng, synonym_set);
+    let value = ordered.iter().position(|w| **w == word).unwrap();
+    let mask = utils::get_max_value(num_bits);
+
+    bitwriter.write(num_bits as u32, (value & mask) as u8).unwrap();
+    // positions after the end of the payload were left untouched,
+    // so their value can be out of range. those bits get cut
+    // off by the payload length anyway.
+  }
+
+  Ok(bitwriter.into_writer())
+}
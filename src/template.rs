@@ -0,0 +1,84 @@
+use clap::ArgMatches;
+
+use super::tokenizer;
+use super::utils;
+
+const CONTEXT_WORDS: usize = 20;
+
+pub struct Template {
+  pub before: String,
+  pub after: String,
+}
+
+pub fn parse_template(contents: &str, marker: &str) -> Result<Template, String> {
+  if marker.is_empty() {
+    return Err("The template marker can not be empty".into());
+  }
+  let parts: Vec<&str> = contents.split(marker).collect();
+  if parts.len() != 2 {
+    return Err(format!(
+      "The template must have the marker '{}' exactly once, it has it {} times",
+      marker,
+      parts.len() - 1,
+    ));
+  }
+
+  Ok(Template {
+    before: parts[0].to_string(),
+    after: parts[1].to_string(),
+  })
+}
+
+pub fn load_template(matches: &ArgMatches) -> Result<Option<Template>, String> {
+  let file_name = match matches.value_of("template") {
+    Some(file_name) => file_name,
+    None => return Ok(None),
+  };
+  let marker = utils::get_value(matches, "template_marker")?;
+  let template = parse_template(&utils::get_file_contents_as_string(file_name)?, marker)?;
+
+  // print summary
+  println!(
+    "template: {} words before and {} words after the stegotext",
+    template.before.split_whitespace().count(),
+    template.after.split_whitespace().count(),
+  );
+
+  Ok(Some(template))
+}
+
+pub fn get_context_words(words: &[&str], policy: &tokenizer::TokenizerPolicy) -> Vec<String> {
+  if words.is_empty() {
+    return vec![];
+  }
+  let words = &words[words.len().saturating_sub(CONTEXT_WORDS)..];
+  tokenizer::tokenize(&words.join(" "), policy)
+    .split_whitespace()
+    .map(String::from)
+    .collect()
+  // only the last few words matter for the ngrams. they are joined
+  // with single spaces first, so the encoder, which has the template,
+  // and the decoder, which only has the words of the text, get the
+  // same context from the same words.
+}
+
+pub fn get_template_context(template: &Template, policy: &tokenizer::TokenizerPolicy) -> Vec<String> {
+  let words: Vec<&str> = template.before.split_whitespace().collect();
+  get_context_words(&words, policy)
+}
+
+pub fn fill_template(template: &Template, text_data: &str) -> String {
+  let mut filled = template.before.clone();
+  if !filled.is_empty() && !filled.ends_with(char::is_whitespace) {
+    filled.push(' ');
+  }
+  filled.push_str(text_data);
+  if !template.after.is_empty() && !template.after.starts_with(char::is_whitespace) {
+    filled.push(' ');
+  }
+  filled.push_str(&template.after);
+  // the stegotext must not run into the words around it, or the
+  // decoder would see one word where the encoder wrote two.
+
+  filled
+}
@@ -0,0 +1,216 @@
+use clap::ArgMatches;
+
+const SENTENCE_PUNCTUATION: [char; 6] = ['.', ',', '!', '?', ';', ':'];
+const QUOTES: [char; 7] = ['"', '\'', '\u{2018}', '\u{2019}', '\u{201c}', '\u{201d}', '`'];
+const APOSTROPHES: [char; 2] = ['\'', '\u{2019}'];
+const URL_PREFIXES: [&str; 4] = ["http://", "https://", "ftp://", "www."];
+const POLICY_RULES: [&str; 6] = ["contractions", "hyphens", "numbers", "urls", "emails", "ellipses"];
+
+#[derive(Clone, Copy)]
+pub struct TokenizerPolicy {
+  pub legacy: bool,
+  pub keep_contractions: bool,
+  pub keep_hyphens: bool,
+  pub keep_numbers: bool,
+  pub keep_urls: bool,
+  pub keep_emails: bool,
+  pub keep_ellipses: bool,
+}
+
+pub fn get_standard_policy() -> TokenizerPolicy {
+  TokenizerPolicy {
+    legacy: false,
+    keep_contractions: true,
+    keep_hyphens: true,
+    keep_numbers: true,
+    keep_urls: true,
+    keep_emails: true,
+    keep_ellipses: true,
+  }
+}
+
+pub fn parse_policy(policy_str: &str) -> Result<TokenizerPolicy, String> {
+  let mut parts = policy_str.split(',').map(str::trim);
+  let mut policy = match parts.next() {
+    Some("standard") => get_standard_policy(),
+    Some("legacy") => TokenizerPolicy { legacy: true, ..get_standard_policy() },
+    _ => return Err(format!("Unknown tokenizer '{}', must start with standard or legacy", policy_str)),
+  };
+
+  for rule in parts {
+    if policy.legacy {
+      return Err("The legacy tokenizer can not be changed, it always works like older versions".into());
+    }
+    let (keep, name) = match rule.strip_prefix("no-") {
+      Some(name) => (false, name),
+      None => (true, rule),
+    };
+    match name {
+      "contractions" => policy.keep_contractions = keep,
+      "hyphens" => policy.keep_hyphens = keep,
+      "numbers" => policy.keep_numbers = keep,
+      "urls" => policy.keep_urls = keep,
+      "emails" => policy.keep_emails = keep,
+      "ellipses" => policy.keep_ellipses = keep,
+      _ => return Err(format!("Unknown tokenizer rule '{}', must be one of: {}", rule, POLICY_RULES.join(", "))),
+    }
+  }
+  // ie: standard,no-hyphens,no-urls
+
+  Ok(policy)
+}
+
+pub fn get_policy(matches: &ArgMatches) -> Result<TokenizerPolicy, String> {
+  match matches.value_of("tokenizer") {
+    Some(policy_str) => parse_policy(policy_str),
+    None => Ok(get_standard_policy()),
+  }
+}
+
+pub fn format_text_for_ngrams(text: &str) -> String {
+  let mut new_text: String = text.to_string().to_lowercase();
+  if text.ends_with('.') {
+    new_text.push(' ');
+  }
+
+  let without_newlines = new_text.replace("\n", " \n ");
+  let without_carriage_returns = without_newlines.replace("\r", "");
+  let without_quotes = without_carriage_returns.replace("\"", "");
+  let without_single_quotes = without_quotes.replace("'", "");
+  let without_dashes = without_single_quotes.replace("-", " ");
+  let without_commas = without_dashes.replace(", ", " , ");
+  let without_exclamation = without_commas.replace("! ", " ! ");
+  let without_questions = without_exclamation.replace("? ", " ? ");
+  let without_semicolons = without_questions.replace("; ", " ; ");
+  let without_colons = without_semicolons.replace(": ", " : ");
+  let mut with_spaces = without_colons.replace(". ", " . ");
+  with_spaces.pop();
+  with_spaces = [". ", &with_spaces].join("");
+  with_spaces
+}
+
+pub fn is_url(chunk: &str) -> bool {
+  URL_PREFIXES.iter().any(|prefix| chunk.starts_with(prefix) && chunk.len() > prefix.len())
+}
+
+pub fn is_email(chunk: &str) -> bool {
+  let mut parts = chunk.splitn(2, '@');
+  let (user, domain) = match (parts.next(), parts.next()) {
+    (Some(user), Some(domain)) => (user, domain),
+    _ => return false,
+  };
+  let is_email_char = |c: char| c.is_alphanumeric() || "._%+-".contains(c);
+
+  !user.is_empty() && user.chars().all(is_email_char)
+    && domain.contains('.') && !domain.starts_with('.') && !domain.ends_with('.')
+    && domain.chars().all(|c| c.is_alphanumeric() || c == '.' || c == '-')
+}
+
+pub fn split_trailing_punctuation(chunk: &str) -> (&str, &str) {
+  let core = chunk.trim_end_matches(|c: char| SENTENCE_PUNCTUATION.contains(&c) || QUOTES.contains(&c) || c == ')');
+  (core, &chunk[core.len()..])
+}
+
+pub fn push_punctuation(tokens: &mut Vec<String>, punctuation: &str, policy: &TokenizerPolicy) {
+  let chars: Vec<char> = punctuation.chars().collect();
+  let mut i = 0;
+  while i < chars.len() {
+    let c = chars[i];
+    if c == '\u{2026}' || (c == '.' && chars[i..].starts_with(&['.', '.', '.'])) {
+      if policy.keep_ellipses {
+        tokens.push(String::from("..."));
+      } else {
+        tokens.push(String::from("."));
+      }
+      i += if c == '.' { 3 } else { 1 };
+      continue;
+    }
+    if SENTENCE_PUNCTUATION.contains(&c) && tokens.last().map(String::as_str) != Some(&c.to_string()) {
+      tokens.push(c.to_string());
+    }
+    i += 1;
+  }
+  // runs like ?! or ,, become one token each, and the same mark is
+  // never repeated, so the model does not learn "! ! !".
+}
+
+pub fn tokenize_chunk(tokens: &mut Vec<String>, chunk: &str, policy: &TokenizerPolicy) {
+  let chars: Vec<char> = chunk.chars().collect();
+  let mut word = String::new();
+  let mut punctuation = String::new();
+  let is_word_char = |c: char| c.is_alphanumeric();
+
+  for (i, c) in chars.iter().enumerate() {
+    let previous = if i > 0 { Some(chars[i - 1]) } else { None };
+    let next = chars.get(i + 1).cloned();
+    let inside_word = previous.is_some_and(is_word_char) && next.is_some_and(is_word_char);
+
+    if is_word_char(*c) {
+      if !punctuation.is_empty() {
+        push_punctuation(tokens, &punctuation, policy);
+        punctuation.clear();
+      }
+      word.push(*c);
+      continue;
+    }
+
+    let keep_in_word = inside_word && match c {
+      c if APOSTROPHES.contains(c) => policy.keep_contractions,
+      '-' => policy.keep_hyphens,
+      '.' | ',' => policy.keep_numbers && previous.is_some_and(|p| p.is_ascii_digit()) && next.is_some_and(|n| n.is_ascii_digit()),
+      _ => false,
+    };
+    if keep_in_word {
+      word.push(if APOSTROPHES.contains(c) { '\'' } else { *c });
+      continue;
+    }
+    if APOSTROPHES.contains(c) && inside_word {
+      continue;
+    }
+    // don't becomes dont without contractions, like the legacy tokenizer
+
+    if !word.is_empty() {
+      tokens.push(word.clone());
+      word.clear();
+    }
+    if !inside_word && (SENTENCE_PUNCTUATION.contains(c) || *c == '\u{2026}') {
+      punctuation.push(*c);
+    }
+    // quotes, brackets, other symbols and punctuation with a letter
+    // on both sides (ie: 3.14 without the numbers rule) only separate words
+  }
+
+  if !word.is_empty() {
+    tokens.push(word);
+  }
+  if !punctuation.is_empty() {
+    push_punctuation(tokens, &punctuation, policy);
+  }
+}
+
+pub fn tokenize(text: &str, policy: &TokenizerPolicy) -> String {
+  if policy.legacy {
+    return format_text_for_ngrams(&text.to_lowercase());
+  }
+
+  let mut tokens = vec![String::from(".")];
+  for chunk in text.to_lowercase().split_whitespace() {
+    let chunk = chunk.trim_start_matches(|c: char| QUOTES.contains(&c) || c == '(');
+    let (core, trailing) = split_trailing_punctuation(chunk);
+    if (policy.keep_urls && is_url(core)) || (policy.keep_emails && is_email(core)) {
+      tokens.push(core.to_string());
+      push_punctuation(&mut tokens, trailing, policy);
+      continue;
+    }
+    // urls and emails are kept whole, only the punctuation
+    // that ends the sentence after them is split off.
+
+    tokenize_chunk(&mut tokens, chunk, policy);
+  }
+  // like the legacy tokenizer, the text starts with a . so the first
+  // word is seen as the start of a sentence. unlike it, punctuation is
+  // split off even without a space after it, and apostrophes inside
+  // words, hyphens, numbers, urls and emails stay in one token.
+
+  tokens.join(" ")
+}
@@ -0,0 +1,151 @@
+use std::collections::{HashMap, HashSet};
+
+use clap::ArgMatches;
+
+use super::pos;
+use super::tokenizer;
+use super::utils;
+
+const WINDOW: usize = 10;
+const MAX_LIFT: f64 = 10.0;
+const MAX_DOCUMENT_KEYWORDS: usize = 30;
+
+pub struct TopicModel<'a> {
+  pub keywords: Vec<&'a str>,
+  pub relatedness: HashMap<&'a str, f64>,
+  pub strength: f64,
+}
+
+pub fn get_document_keywords(text: &str, policy: &tokenizer::TokenizerPolicy) -> Vec<String> {
+  let tokens = tokenizer::tokenize(text, policy);
+  let mut counts: HashMap<&str, usize> = HashMap::new();
+  for word in tokens.split_whitespace() {
+    if !utils::is_punctuation(word) && !pos::is_function_word(word) {
+      *counts.entry(word).or_insert(0) += 1;
+    }
+  }
+
+  let mut keywords: Vec<(&str, usize)> = counts.into_iter().collect();
+  keywords.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+  keywords.into_iter().take(MAX_DOCUMENT_KEYWORDS).map(|(word, _)| word.to_string()).collect()
+  // the most common words of the document that are not words like
+  // "the" or "of", which say nothing about what the document is about.
+}
+
+pub fn get_keywords(matches: &ArgMatches) -> Result<Vec<String>, String> {
+  let mut keywords = vec![];
+  if let Some(topics) = matches.values_of("topic") {
+    for topic in topics {
+      keywords.extend(topic.split(|c: char| c == ',' || c.is_whitespace()).filter(|w| !w.is_empty()).map(str::to_lowercase));
+    }
+  }
+  if let Some(file_name) = matches.value_of("topic_file") {
+    let policy = tokenizer::get_policy(matches)?;
+    keywords.extend(get_document_keywords(&utils::get_file_contents_as_string(file_name)?, &policy));
+  }
+  // ie: --topic rain,sun --topic weather, or a document about the topic
+
+  Ok(keywords)
+}
+
+pub fn generate_topic_model<'a>(
+  text: &'a str,
+  keywords: &[String],
+  word_counts: &HashMap<Vec<&'a str>, usize>,
+  strength: f64,
+) -> Result<TopicModel<'a>, String> {
+  let words: Vec<&str> = text.split_whitespace().collect();
+  let mut found_keywords: HashSet<&str> = HashSet::new();
+  for word in &words {
+    if keywords.iter().any(|k| k == word) {
+      found_keywords.insert(word);
+    }
+  }
+  for keyword in keywords {
+    if !found_keywords.contains(keyword.as_str()) {
+      println!("topic word '{}' is not in the mimic text, ignoring it", keyword);
+    }
+  }
+  if found_keywords.is_empty() {
+    return Err("None of the topic words are in the mimic text".into());
+  }
+
+  let mut near_keyword = vec![false; words.len()];
+  for (i, word) in words.iter().enumerate() {
+    if found_keywords.contains(word) {
+      let start = i.saturating_sub(WINDOW);
+      let end = (i + WINDOW + 1).min(words.len());
+      for (j, near) in near_keyword[start..end].iter_mut().enumerate() {
+        *near = *near || start + j != i;
+      }
+    }
+  }
+  let num_near = near_keyword.iter().filter(|near| **near).count();
+  let base_rate = num_near as f64 / words.len() as f64;
+
+  let mut co_occurrences: HashMap<&str, usize> = HashMap::new();
+  for (word, near) in words.iter().zip(near_keyword.iter()) {
+    if *near {
+      *co_occurrences.entry(word).or_insert(0) += 1;
+    }
+  }
+
+  let mut relatedness = HashMap::new();
+  for (key, count) in word_counts {
+    if key.len() != 1 {
+      continue;
+    }
+    let word = key[0];
+    let co_occurrence = co_occurrences.get(word).cloned().unwrap_or(0) as f64;
+    let lift = (co_occurrence + 1.0) / (base_rate * *count as f64 + 1.0);
+    let lift = if found_keywords.contains(word) { MAX_LIFT } else { lift.min(MAX_LIFT) };
+    relatedness.insert(word, lift);
+  }
+  // lift: how much more often a word is within a few words of a topic
+  // word than it would be by chance. words like "the" are near the topic
+  // about as often as anywhere else and get about 1, related words get
+  // more, and common words that never come up near the topic get less.
+
+  let mut keywords: Vec<&str> = found_keywords.into_iter().collect();
+  keywords.sort();
+  Ok(TopicModel {
+    keywords,
+    relatedness,
+    strength,
+  })
+}
+
+pub fn get_topic_model<'a>(
+  matches: &ArgMatches,
+  text: &'a str,
+  word_counts: &HashMap<Vec<&'a str>, usize>,
+) -> Result<Option<TopicModel<'a>>, String> {
+  let keywords = get_keywords(matches)?;
+  if keywords.is_empty() {
+    return Ok(None);
+  }
+  let strength: f64 = utils::get_value(matches, "topic_strength")?
+    .parse()
+    .map_err(|_| "--topic-strength must be a number".to_string())?;
+  if strength < 0.0 {
+    return Err("--topic-strength can not be negative".into());
+  }
+
+  let topic_model = generate_topic_model(text, &keywords, word_counts, strength)?;
+  let mut related: Vec<(&str, f64)> = topic_model.relatedness.iter()
+    .filter(|(word, _)| !utils::is_punctuation(word) && !topic_model.keywords.contains(word))
+    .filter(|(word, _)| word_counts.get(&vec![**word]).cloned().unwrap_or(0) > 1)
+    .map(|(word, lift)| (*word, *lift))
+    .collect();
+  related.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap().then(a.0.cmp(b.0)));
+  let related: Vec<&str> = related.iter().take(10).map(|(word, _)| *word).collect();
+  // print summary
+  println!("topic: {}", topic_model.keywords.join(", "));
+  println!("most related words: {}", related.join(", "));
+
+  Ok(Some(topic_model))
+}
+
+pub fn get_topic_bias(topic_model: &TopicModel, word: &str) -> f64 {
+  topic_model.relatedness.get(word).cloned().unwrap_or(1.0).powf(topic_model.strength)
+}
@@ -5,6 +5,8 @@ use clap::ArgMatches;
 use rand::{Rng, SeedableRng, prelude::StdRng};
 use arrayref::array_ref;
 use sha2::{Sha256, Digest};
+use hmac::{Hmac, Mac};
+use zeroize::Zeroizing;
 
 
 const COMMON_CHARS: [char; 26] = [
@@ -14,6 +16,12 @@ const COMMON_CHARS: [char; 26] = [
   'k', 'j', 'x', 'q', 'z'
 ];
 
+const FRAME_LABEL: &[u8] = b"text-steg frame";
+const FRAME_NONCE_LEN: usize = 4;
+const FRAME_FIELDS_LEN: usize = 9;
+pub const FRAME_HEADER_LEN: usize = FRAME_NONCE_LEN + FRAME_FIELDS_LEN;
+
+#[derive(Clone, Copy)]
 pub enum ValueMode {
   CharBitMap,
   // the map contains 1 character for each bit position
@@ -28,11 +36,69 @@ pub enum ValueMode {
   // by adding the the value for each character present in a word. duplicate characters
   // are allowed, since it will increase the value. If the value reaches
   // 2^(num bits), it overflows and wraps back to 0.
+  AdaptiveCharValue(usize),
+  // same character to value map as CharValueMap, but the number of bits
+  // is not fixed. instead, for every word, the number of bits is chosen
+  // based on how many candidate words the n-gram context offers, up to the
+  // given maximum. the decoder needs the same n-gram model to re-derive
+  // the number of bits from the preceding words.
+  WordAcrostic(usize),
+  // every letter of the alphabet is assigned a value from 0 to 2^(num bits) - 1,
+  // but only the first letter of a word is used. the rest of the word
+  // is free, which gives a lot more choice than constraining every letter.
+  // only a keyed subset of the words carries a value, see is_data_position.
+  SentenceAcrostic(usize),
+  // same as WordAcrostic, but only the first word of every sentence
+  // carries a value. the words in between are picked freely from the n-grams.
+  WordHash(usize),
+  // the value of a word is a keyed hash (HMAC of the word, keyed with the seed)
+  // modulo 2^(num bits). the letters of a word do not matter at all, so
+  // every word can be used, and which words map to which value is secret.
 }
 
+pub const DATA_POSITION_ODDS: usize = 2;
+// one in this many words of an acrostic carries a value.
+
+pub const MAX_ACROSTIC_BITS: usize = 4;
+// 26 letters can cover at most 16 different values with at
+// least one letter each.
+
+#[derive(Clone, Copy)]
 pub enum Algorithm {
   Shuffle(ValueMode),
   NoShuffle(ValueMode),
+  Synonym,
+  // does not generate any new text. instead, an existing cover text
+  // is kept as is, and bits are hidden by swapping words for
+  // other members of their synonym set.
+  Invisible(Carrier),
+  // also keeps an existing cover text, but hides bits in characters
+  // that do not change how the text reads.
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum PayloadKind {
+  Data,
+  // the bytes that were given to encode
+  Share,
+  // one share of a payload that was split with --split
+  Archive,
+  // several files, or one file with --archive, see archive.rs
+}
+
+#[derive(Clone, Copy)]
+pub enum Carrier {
+  ZeroWidth,
+  // zero width spaces, joiners and non joiners inserted at the end of words.
+  // 4 different characters, so each one holds 2 bits.
+  VariationSelector,
+  // unicode variation selectors inserted at the end of words. they
+  // have no effect on letters. only VS1 to VS8 are used, so each holds 3 bits.
+  Homoglyph,
+  // latin letters that have a cyrillic lookalike. every such letter
+  // in the cover text holds 1 bit: either it stays latin or it gets swapped.
+  TrailingWhitespace,
+  // spaces and tabs at the end of lines, each holds 1 bit.
 }
 
 pub fn get_algorithm_from_string(alg_str: &str, num_bits: usize) -> Result<Algorithm, String> {
@@ -41,6 +107,22 @@ pub fn get_algorithm_from_string(alg_str: &str, num_bits: usize) -> Result<Algor
     "char-bit-shuffle" => Ok(Algorithm::Shuffle(ValueMode::CharBitMap)),
     "char-value" => Ok(Algorithm::NoShuffle(ValueMode::CharValueMap(num_bits))),
     "char-value-shuffle" => Ok(Algorithm::Shuffle(ValueMode::CharValueMap(num_bits))),
+    "char-value-adaptive" => Ok(Algorithm::NoShuffle(ValueMode::AdaptiveCharValue(num_bits))),
+    "char-value-adaptive-shuffle" => Ok(Algorithm::Shuffle(ValueMode::AdaptiveCharValue(num_bits))),
+    "acrostic" | "acrostic-shuffle" | "sentence-acrostic" | "sentence-acrostic-shuffle" if num_bits > MAX_ACROSTIC_BITS => {
+      Err(format!("The acrostic algorithms can use at most {} bits, you provided {}", MAX_ACROSTIC_BITS, num_bits))
+    },
+    "acrostic" => Ok(Algorithm::NoShuffle(ValueMode::WordAcrostic(num_bits))),
+    "acrostic-shuffle" => Ok(Algorithm::Shuffle(ValueMode::WordAcrostic(num_bits))),
+    "sentence-acrostic" => Ok(Algorithm::NoShuffle(ValueMode::SentenceAcrostic(num_bits))),
+    "sentence-acrostic-shuffle" => Ok(Algorithm::Shuffle(ValueMode::SentenceAcrostic(num_bits))),
+    "word-hash" => Ok(Algorithm::NoShuffle(ValueMode::WordHash(num_bits))),
+    "word-hash-shuffle" => Ok(Algorithm::Shuffle(ValueMode::WordHash(num_bits))),
+    "synonym" => Ok(Algorithm::Synonym),
+    "zero-width" => Ok(Algorithm::Invisible(Carrier::ZeroWidth)),
+    "variation-selector" => Ok(Algorithm::Invisible(Carrier::VariationSelector)),
+    "homoglyph" => Ok(Algorithm::Invisible(Carrier::Homoglyph)),
+    "trailing-whitespace" => Ok(Algorithm::Invisible(Carrier::TrailingWhitespace)),
     _ => Err(format!("Could not determine algorithm: {}", alg_str)),
   }
 }
@@ -65,20 +147,61 @@ pub fn get_numerical_value(matches: &ArgMatches, value_name: &str) -> Result<usi
 
 pub fn make_bit_to_char_map(num_bits: usize) -> HashMap<usize, char> {
   let mut bit_to_char_map: HashMap<usize, char> = HashMap::new();
-  for num in 0..(num_bits + 1) {
+  for (num, common_char) in COMMON_CHARS.iter().enumerate().take(num_bits + 1) {
     let key = if num == 0 {
       0
     } else {
       1 << (num - 1)
     };
-    bit_to_char_map.insert(key, COMMON_CHARS[num as usize]);
+    bit_to_char_map.insert(key, *common_char);
   }
 
   bit_to_char_map
 }
 
+pub fn make_first_letter_map(exponent: usize) -> HashMap<char, usize> {
+  let mut first_letter_map: HashMap<char, usize> = HashMap::new();
+
+  let max_val = get_max_value(exponent);
+  for (i, c) in COMMON_CHARS.iter().enumerate() {
+    first_letter_map.insert(*c, i % (max_val + 1));
+  }
+  // unlike make_char_to_value_map, every value needs at least one letter
+  // that maps to it since a word only has one first letter.
+  // going through the letters in order of how common they are
+  // also spreads the common letters over all of the values.
+
+  first_letter_map
+}
+
+pub fn make_value_map_for_mode(mode: &ValueMode, exponent: usize) -> HashMap<char, usize> {
+  match mode {
+    ValueMode::WordAcrostic(_) | ValueMode::SentenceAcrostic(_) => make_first_letter_map(exponent),
+    _ => make_char_to_value_map(exponent),
+  }
+}
+
+pub fn is_sentence_end(word: &str) -> bool {
+  word == "." || word == "?" || word == "!"
+}
+
+pub fn get_sentence_starts<'a>(words: &[&'a str]) -> Vec<&'a str> {
+  let mut sentence_starts = vec![];
+  let mut is_start = true;
+  for word in words {
+    if is_sentence_end(word) {
+      is_start = true;
+    } else if is_start {
+      sentence_starts.push(*word);
+      is_start = false;
+    }
+  }
+
+  sentence_starts
+}
+
 pub fn get_max_value(exponent: usize) -> usize {
-  (2 as usize).pow(exponent as u32) - 1
+  2_usize.pow(exponent as u32) - 1
 }
 
 pub fn make_char_to_value_map(exponent: usize) -> HashMap<char, usize> {
@@ -86,7 +209,7 @@ pub fn make_char_to_value_map(exponent: usize) -> HashMap<char, usize> {
 
   let max_val = get_max_value(exponent);
   let mut current_val = 0;
-  let mut max_it = COMMON_CHARS.len() / 2;
+  let max_it = COMMON_CHARS.len() / 2;
   for i in 0..max_it {
     let common_index = i;
     let uncommon_index = COMMON_CHARS.len() - i - 1;
@@ -120,33 +243,147 @@ fn create_hash(text: &str) -> String {
 }
 
 pub fn create_rng_from_seed(text: &str) -> StdRng {
-  let hash = create_hash(text);
+  let hash = Zeroizing::new(create_hash(text));
   let seed = array_ref!(hash.as_bytes(), 0, 32);
   SeedableRng::from_seed(*seed)
 }
 
-pub fn format_text_for_ngrams(text: &str) -> String {
-  let mut new_text: String = text.to_string().to_lowercase();
-  if text.ends_with('.') {
-    new_text.push(' ');
+pub fn create_position_rng(seed_str: &str) -> StdRng {
+  create_rng_from_seed(&Zeroizing::new(format!("{}:positions", seed_str)))
+}
+
+pub fn is_data_position(rng: &mut StdRng) -> bool {
+  rng.gen_range(0, DATA_POSITION_ODDS) == 0
+  // the words in between are picked freely, so which words carry
+  // the first letters is only known with the seed.
+}
+
+pub fn get_word_spans(text: &str) -> Vec<(usize, usize)> {
+  let mut spans = vec![];
+  let mut word_start = None;
+  for (i, c) in text.char_indices() {
+    match (c.is_alphabetic(), word_start) {
+      (true, None) => word_start = Some(i),
+      (false, Some(start)) => {
+        spans.push((start, i));
+        word_start = None;
+      },
+      _ => (),
+    }
+  }
+  if let Some(start) = word_start {
+    spans.push((start, text.len()));
+  }
+  // a word is any run of alphabetic characters. everything in between
+  // (whitespace, punctuation, numbers) is copied to the output as is.
+
+  spans
+}
+
+pub fn shuffle_in_place<T>(rng: &mut StdRng, items: &mut [T]) {
+  for i in (1..items.len()).rev() {
+    let random_index = rng.gen_range(0, i + 1);
+    items.swap(i, random_index);
+  }
+}
+
+pub fn get_frame_mask(seed_str: &str, nonce: &[u8]) -> [u8; FRAME_FIELDS_LEN] {
+  let mut mac = Hmac::<Sha256>::new_varkey(seed_str.as_bytes()).unwrap();
+  mac.input(FRAME_LABEL);
+  mac.input(nonce);
+  let code = mac.result().code();
+  // the mask depends on the seed, so a header can not be found
+  // without it, and on the nonce, so it is different for every message.
+
+  *array_ref!(code, 0, FRAME_FIELDS_LEN)
+}
+
+pub fn frame_payload(contents: &[u8], seed_str: &str, kind: PayloadKind) -> Vec<u8> {
+  let mut nonce = [0u8; FRAME_NONCE_LEN];
+  rand::rngs::OsRng.fill(&mut nonce);
+
+  let mut fields = vec![0u8; 4];
+  fields.push(kind as u8);
+  fields.extend_from_slice(&(contents.len() as u32).to_be_bytes());
+  for (field, mask) in fields.iter_mut().zip(get_frame_mask(seed_str, &nonce).iter()) {
+    *field ^= mask;
+  }
+  // 4 zero bytes to recognize the header by, the kind and the length,
+  // all masked. with a fixed header, every message encoded with the
+  // same seed would start with the same words.
+
+  let mut framed = nonce.to_vec();
+  framed.extend_from_slice(&fields);
+  framed.extend_from_slice(contents);
+  // the decoder reads whatever is left after the payload as well,
+  // like the filler or the rest of a cover text. the length tells
+  // it where to stop.
+
+  framed
+}
+
+pub fn read_frame_header(header: &[u8], seed_str: &str) -> Result<(PayloadKind, usize), String> {
+  if header.len() < FRAME_HEADER_LEN {
+    return Err("Could not find a payload header. Was the seed correct?".into());
   }
 
-  let without_newlines = new_text.replace("\n", " \n ");
-  let without_carriage_returns = without_newlines.replace("\r", "");
-  let without_quotes = without_carriage_returns.replace("\"", "");
-  let without_single_quotes = without_quotes.replace("'", "");
-  let without_dashes = without_single_quotes.replace("-", " ");
-  let without_commas = without_dashes.replace(", ", " , ");
-  let without_exclamation = without_commas.replace("! ", " ! ");
-  let without_questions = without_exclamation.replace("? ", " ? ");
-  let without_semicolons = without_questions.replace("; ", " ; ");
-  let without_colons = without_semicolons.replace(": ", " : ");
-  let mut with_spaces = without_colons.replace(". ", " . ");
-  with_spaces.pop();
-  with_spaces = [". ", &with_spaces].join("");
-  with_spaces
+  let mask = get_frame_mask(seed_str, &header[..FRAME_NONCE_LEN]);
+  let mut fields = [0u8; FRAME_FIELDS_LEN];
+  for (i, field) in fields.iter_mut().enumerate() {
+    *field = header[FRAME_NONCE_LEN + i] ^ mask[i];
+  }
+  if fields[..4] != [0u8; 4] {
+    return Err("Could not find a payload header. Was the seed correct?".into());
+  }
+
+  let kind = get_payload_kind(fields[4])?;
+  let length = u32::from_be_bytes(*array_ref!(fields, 5, 4)) as usize;
+  Ok((kind, FRAME_HEADER_LEN + length))
+}
+
+pub fn get_framed_length(header: &[u8], seed_str: &str) -> Result<usize, String> {
+  let (_, framed_length) = read_frame_header(header, seed_str)?;
+
+  Ok(framed_length)
 }
 
+pub fn get_payload_kind(byte: u8) -> Result<PayloadKind, String> {
+  match byte {
+    0 => Ok(PayloadKind::Data),
+    1 => Ok(PayloadKind::Share),
+    2 => Ok(PayloadKind::Archive),
+    _ => Err(format!("Unknown payload kind {}", byte)),
+  }
+}
+
+pub fn unframe_payload(data: &[u8], seed_str: &str) -> Result<(PayloadKind, Vec<u8>), String> {
+  let (kind, framed_length) = read_frame_header(data, seed_str)?;
+  if data.len() < framed_length {
+    return Err(format!(
+      "Payload header says there are {} bytes, but only {} were found",
+      framed_length - FRAME_HEADER_LEN,
+      data.len() - FRAME_HEADER_LEN,
+    ));
+  }
+
+  Ok((kind, data[FRAME_HEADER_LEN..framed_length].to_vec()))
+}
+
+pub fn is_punctuation(word: &str) -> bool {
+  word == "." || word == "," || word == "?" || word == ";" || word == "!" || word == ":" || word == "..."
+}
+
+pub fn get_bits_for_candidates(num_candidates: usize, max_bits: usize) -> usize {
+  let mut num_bits = 1;
+  while num_bits < max_bits && (2 << num_bits) <= num_candidates {
+    num_bits += 1;
+  }
+  // the largest number of bits such that there are at least as many
+  // candidates as there are values to encode, ie: 2^bits <= candidates.
+  // always encode at least 1 bit so that every word carries data.
+
+  num_bits
+}
 
 pub fn is_skip_word(word: &str, char_to_bit_map: &HashMap<char, usize>) -> bool {
   let mut restricted_chars = vec![]; 
@@ -178,11 +415,10 @@ pub fn get_file_contents_as_string(file_name: &str) -> Result<String, String> {
   }
 }
 
-pub fn get_chars_from_value(val: u8, char_map: &HashMap<usize, char>, sorted_keys: &Vec<usize>) -> String {
+pub fn get_chars_from_value(val: u8, char_map: &HashMap<usize, char>, sorted_keys: &[usize]) -> String {
   let mut out_str = String::from("");
   let mut val_remaining = val;
-  for num in 0..sorted_keys.len() {
-    let current_byte_val = sorted_keys[num];
+  for &current_byte_val in sorted_keys {
 
     if current_byte_val as u8 == val_remaining {
       let some_char = char_map.get(&current_byte_val).unwrap();
@@ -205,20 +441,21 @@ pub fn get_chars_from_value(val: u8, char_map: &HashMap<usize, char>, sorted_key
 pub fn get_value_from_chars(chars: &str, char_map: &HashMap<char, usize>, mode: &ValueMode) -> usize {
   let mut out_value = 0;
   let mut chars_checked = vec![];
-  for c in chars.chars() {
-
-    match mode {
-      ValueMode::CharBitMap => {
-        if chars_checked.contains(&c) {
-          // if we already checked this character,
-          // dont bother checking again. since
-          // each character represents a bit being set. if there are
-          // multiple characters that does not mean that
-          // the bit is set multiple times...
-          continue
-        }
-      },
-      _ => (),
+  let num_chars_to_check = match mode {
+    ValueMode::WordAcrostic(_) | ValueMode::SentenceAcrostic(_) => 1,
+    _ => chars.len(),
+  };
+  for c in chars.chars().take(num_chars_to_check) {
+
+    if let ValueMode::CharBitMap = mode {
+      if chars_checked.contains(&c) {
+        // if we already checked this character,
+        // dont bother checking again. since
+        // each character represents a bit being set. if there are
+        // multiple characters that does not mean that
+        // the bit is set multiple times...
+        continue
+      }
     }
 
 
@@ -231,6 +468,59 @@ pub fn get_value_from_chars(chars: &str, char_map: &HashMap<char, usize>, mode:
   match mode {
     ValueMode::CharBitMap => out_value,
     ValueMode::CharValueMap(exp) => out_value % (get_max_value(*exp) + 1),
+    ValueMode::AdaptiveCharValue(exp) => out_value % (get_max_value(*exp) + 1),
+    ValueMode::WordAcrostic(exp) | ValueMode::SentenceAcrostic(exp) => out_value % (get_max_value(*exp) + 1),
+    ValueMode::WordHash(_) => out_value,
+    // word hash values do not depend on the characters. see get_word_value
+  }
+}
+
+pub fn get_word_hash_value(word: &str, hash_key: &[u8], index: Option<usize>, exponent: usize) -> usize {
+  let mut mac = Hmac::<Sha256>::new_varkey(hash_key).unwrap();
+  if let Some(index) = index {
+    // when shuffling, the index of the value being encoded is part
+    // of the hash, so every position gets a different partition of words.
+    mac.input(&(index as u64).to_be_bytes());
+  }
+  mac.input(word.as_bytes());
+  let code = mac.result().code();
+  let value = u16::from_be_bytes([code[0], code[1]]) as usize;
+
+  value % (get_max_value(exponent) + 1)
+}
+
+pub fn get_word_hash_values<'a>(
+  words: &[&'a str],
+  mode: &ValueMode,
+  hash_key: &[u8],
+  use_shuffle: bool,
+) -> Option<HashMap<&'a str, usize>> {
+  let exponent = match mode {
+    ValueMode::WordHash(exp) if !use_shuffle => *exp,
+    _ => return None,
+  };
+
+  let mut values = HashMap::new();
+  for word in words {
+    values.entry(*word).or_insert_with(|| get_word_hash_value(word, hash_key, None, exponent));
+  }
+
+  Some(values)
+  // without shuffling, the value of a word is the same at every
+  // position, so every word only has to be hashed once instead of
+  // once for every value that gets encoded.
+}
+
+pub fn get_word_value(
+  word: &str,
+  char_map: &HashMap<char, usize>,
+  mode: &ValueMode,
+  hash_key: &[u8],
+  index: Option<usize>,
+) -> usize {
+  match mode {
+    ValueMode::WordHash(exp) => get_word_hash_value(word, hash_key, index, *exp),
+    _ => get_value_from_chars(word, char_map, mode),
   }
 }
 
@@ -238,21 +528,21 @@ pub fn shuffle_char_value_map(rng: &mut StdRng, char_to_value_map: &mut HashMap<
   let mut char_values = vec![];
   let mut char_keys = vec![];
   for key in char_to_value_map.keys() {
-    char_keys.push(key.clone());
+    char_keys.push(*key);
   }
 
-  char_keys.sort_by(|a, b| a.cmp(b));
+  char_keys.sort();
   for key in &char_keys {
-    char_values.push(char_to_value_map.get(key).unwrap().clone());
+    char_values.push(*char_to_value_map.get(key).unwrap());
   }
 
   let mut chars = COMMON_CHARS.to_vec();
-  while chars.len() > 0 {
+  while !chars.is_empty() {
     let current_char = chars[0];
     let random_index = rng.gen_range(0, chars.len());
     let random_char = chars[random_index];
-    let current_val = char_to_value_map.get(&current_char).unwrap().clone();
-    let random_val = char_to_value_map.get(&random_char).unwrap().clone();
+    let current_val = *char_to_value_map.get(&current_char).unwrap();
+    let random_val = *char_to_value_map.get(&random_char).unwrap();
     chars.remove(random_index);
     chars.remove(0);
     char_to_value_map.remove(&current_char);
@@ -266,19 +556,18 @@ pub fn fill_bit_to_char_map(rng: &mut StdRng, bit_to_char_map: &mut HashMap<usiz
   let mut bit_keys = vec![];
   let mut bit_values = vec![];
   for key in bit_to_char_map.keys() {
-    bit_keys.push(key.clone());
+    bit_keys.push(*key);
   }
 
-  bit_keys.sort_by(|a, b| a.cmp(b));
+  bit_keys.sort();
 
   for key in &bit_keys {
-    bit_values.push(bit_to_char_map.get(key).unwrap().clone());
+    bit_values.push(*bit_to_char_map.get(key).unwrap());
   }
 
   let mut chars = COMMON_CHARS.to_vec();
 
-  for num in 0..bit_keys.len() {
-    let key = bit_keys[num];
+  for &key in &bit_keys {
     let random_index = rng.gen_range(0, chars.len());
     let random_char = chars[random_index];
     chars.remove(random_index);
@@ -291,8 +580,35 @@ pub fn fill_bit_to_char_map(rng: &mut StdRng, bit_to_char_map: &mut HashMap<usiz
 pub fn make_char_to_bit_map(bit_to_char_map: &HashMap<usize, char>) -> HashMap<char, usize> {
   let mut char_to_bit_map = HashMap::new();
   for bit_val in bit_to_char_map.keys() {
-    char_to_bit_map.insert(*bit_to_char_map.get(&bit_val).unwrap() , *bit_val);
+    char_to_bit_map.insert(*bit_to_char_map.get(bit_val).unwrap() , *bit_val);
   }
 
   char_to_bit_map
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn frame_round_trips() {
+    let framed = frame_payload(b"payload", "seed", PayloadKind::Archive);
+    assert_eq!(framed.len(), FRAME_HEADER_LEN + 7);
+    assert_eq!(get_framed_length(&framed, "seed").unwrap(), framed.len());
+    let (kind, payload) = unframe_payload(&[framed.as_slice(), b"filler"].concat(), "seed").unwrap();
+    assert!(kind == PayloadKind::Archive);
+    assert_eq!(payload, b"payload");
+
+    assert!(get_framed_length(&framed, "other seed").is_err());
+    assert!(get_framed_length(&framed[..FRAME_HEADER_LEN - 1], "seed").is_err());
+    assert!(unframe_payload(&framed[..framed.len() - 1], "seed").is_err());
+  }
+
+  #[test]
+  fn frame_header_differs_between_messages() {
+    let first = frame_payload(b"payload", "seed", PayloadKind::Data);
+    let second = frame_payload(b"payload", "seed", PayloadKind::Data);
+    assert_ne!(first[..FRAME_HEADER_LEN], second[..FRAME_HEADER_LEN]);
+    assert_eq!(first[FRAME_HEADER_LEN..], second[FRAME_HEADER_LEN..]);
+  }
+}
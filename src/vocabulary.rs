@@ -0,0 +1,114 @@
+use std::collections::{HashMap, HashSet};
+
+use clap::ArgMatches;
+
+use super::utils;
+
+pub struct Vocabulary {
+  pub min_count: usize,
+  pub max_word_length: Option<usize>,
+  pub blocklist: HashSet<String>,
+  pub allowlist: Option<HashSet<String>>,
+}
+
+pub fn load_word_list(file_name: &str) -> Result<HashSet<String>, String> {
+  let contents = utils::get_file_contents_as_string(file_name)?;
+  let mut words = HashSet::new();
+  for line in contents.lines() {
+    let line = line.trim();
+    if line.starts_with('#') {
+      continue;
+    }
+    words.extend(line.split_whitespace().map(|w| w.to_lowercase()));
+  }
+  // one or more words per line, lines starting with # are comments,
+  // same as the synonym dictionary.
+
+  Ok(words)
+}
+
+pub fn load_word_lists(matches: &ArgMatches, name: &str) -> Result<Option<HashSet<String>>, String> {
+  let file_names = match matches.values_of(name) {
+    Some(file_names) => file_names,
+    None => return Ok(None),
+  };
+
+  let mut words = HashSet::new();
+  for file_name in file_names {
+    words.extend(load_word_list(file_name)?);
+  }
+  Ok(Some(words))
+}
+
+pub fn get_vocabulary(matches: &ArgMatches) -> Result<Vocabulary, String> {
+  let min_count = utils::get_numerical_value(matches, "min_count")?;
+  if min_count == 0 {
+    return Err("--min-count must be at least 1".into());
+  }
+  let max_word_length = match matches.value_of("max_word_length") {
+    Some(_) => Some(utils::get_numerical_value(matches, "max_word_length")?),
+    None => None,
+  };
+
+  Ok(Vocabulary {
+    min_count,
+    max_word_length,
+    blocklist: load_word_lists(matches, "blocklist")?.unwrap_or_default(),
+    allowlist: load_word_lists(matches, "allowlist")?,
+  })
+}
+
+pub fn filter_words<'a>(
+  unique_words: &[&'a str],
+  gram: &HashMap<Vec<&str>, usize>,
+  vocabulary: &Vocabulary,
+) -> Vec<&'a str> {
+  let mut words = vec![];
+  let mut num_rare = 0;
+  let mut num_long = 0;
+  let mut num_blocked = 0;
+  let mut num_not_allowed = 0;
+  let mut num_words = 0;
+
+  for w in unique_words {
+    if utils::is_punctuation(w) {
+      words.push(*w);
+      continue;
+    }
+    num_words += 1;
+
+    let count = gram.get(&vec![*w]).cloned().unwrap_or(0);
+    if count < vocabulary.min_count {
+      num_rare += 1;
+    } else if vocabulary.max_word_length.is_some_and(|max| w.chars().count() > max) {
+      num_long += 1;
+    } else if vocabulary.blocklist.contains(*w) {
+      num_blocked += 1;
+    } else if vocabulary.allowlist.as_ref().is_some_and(|allowlist| !allowlist.contains(*w)) {
+      num_not_allowed += 1;
+    } else {
+      words.push(*w);
+    }
+  }
+  // the filtered words are only removed from the words that can be
+  // picked. they stay in the ngram hash, so the probabilities of the
+  // other words, and the bits per word of the adaptive algorithms,
+  // are the same as without the filter, and the decoder does not need it.
+
+  let num_removed = num_rare + num_long + num_blocked + num_not_allowed;
+  if num_removed > 0 {
+    // print summary
+    println!(
+      "vocabulary: using {} of {} words ({} seen less than {} times, {} too long, {} in the blocklist, {} not in the allowlist)",
+      num_words - num_removed,
+      num_words,
+      num_rare,
+      vocabulary.min_count,
+      num_long,
+      num_blocked,
+      num_not_allowed,
+    );
+  }
+
+  words
+}